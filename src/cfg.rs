@@ -0,0 +1,232 @@
+use crate::observer::JumpKind;
+use crate::structures::*;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where an edge of a control-flow graph goes.
+pub enum Target {
+    /// The start of a block, by its index.
+    Block(usize),
+    /// Past the end of the program, which halts it.
+    Exit,
+    /// Wherever a value says at runtime, for goto and return.
+    Dynamic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A way execution can leave a block.
+pub struct Edge {
+    /// The kind of jump taken, or `None` when falling through to the next instruction,
+    /// which includes branches that weren't taken.
+    pub kind: Option<JumpKind>,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A run of instructions that always execute one after another,
+/// only entered at the start and only left at the end.
+pub struct Block {
+    /// The index of the first instruction.
+    pub start: usize,
+    /// The index after the last instruction.
+    pub end: usize,
+    /// Every way execution can leave the block. Empty if it ends with break.
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The control-flow graph of a program, made of basic blocks.
+pub struct Cfg {
+    blocks: Vec<Block>,
+    /// The block each instruction is in.
+    block_of: Vec<usize>,
+}
+
+impl Cfg {
+    /// Build the control-flow graph of a program's instructions.
+    pub fn new(instructions: &[(usize, Instruction)]) -> Self {
+        let len = instructions.len();
+        let mut leaders = vec![false; len];
+        if len > 0 {
+            leaders[0] = true;
+        }
+        for (index, (_, instruction)) in instructions.iter().enumerate() {
+            let target = match *instruction {
+                Instruction::Jump(to) | Instruction::Branch(to) | Instruction::Call(to) => Some(to),
+                Instruction::Goto(_) | Instruction::Return | Instruction::Break => None,
+                _ => continue,
+            };
+            if let Some(leader) = target.and_then(|to| leaders.get_mut(to)) {
+                *leader = true;
+            }
+            // Also where calls return to, and where branches fall through to
+            if let Some(leader) = leaders.get_mut(index + 1) {
+                *leader = true;
+            }
+        }
+        let starts: Vec<usize> = (0..len).filter(|&index| leaders[index]).collect();
+        let mut block_of = vec![0; len];
+        for (block, &start) in starts.iter().enumerate() {
+            block_of[start..].fill(block);
+        }
+        let target = |index: usize| match block_of.get(index) {
+            Some(&block) => Target::Block(block),
+            None => Target::Exit,
+        };
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block, &start)| {
+                let end = starts.get(block + 1).copied().unwrap_or(len);
+                let next = Edge {
+                    kind: None,
+                    target: target(end),
+                };
+                let jump = |kind, to| Edge {
+                    kind: Some(kind),
+                    target: target(to),
+                };
+                let dynamic = |kind| Edge {
+                    kind: Some(kind),
+                    target: Target::Dynamic,
+                };
+                let edges = match instructions[end - 1].1 {
+                    Instruction::Jump(to) => vec![jump(JumpKind::Jump, to)],
+                    Instruction::Branch(to) => vec![jump(JumpKind::Branch, to), next],
+                    Instruction::Call(to) => vec![jump(JumpKind::Call, to)],
+                    Instruction::Goto(_) => vec![dynamic(JumpKind::Goto)],
+                    Instruction::Return => vec![dynamic(JumpKind::Return)],
+                    Instruction::Break => Vec::new(),
+                    _ => vec![next],
+                };
+                Block { start, end, edges }
+            })
+            .collect();
+        Self { blocks, block_of }
+    }
+
+    /// Get every block, in the order they appear in the program.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Get the block an instruction is in.
+    pub fn block_of(&self, index: usize) -> Option<usize> {
+        self.block_of.get(index).copied()
+    }
+
+    /// Get the blocks with an edge to a block, in order. Dynamic edges aren't included.
+    pub fn predecessors(&self, block: usize) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|&from| {
+                self.blocks[from]
+                    .edges
+                    .iter()
+                    .any(|edge| edge.target == Target::Block(block))
+            })
+            .collect()
+    }
+
+    /// Get the names of the labels pointing into a block, sorted by where they point.
+    fn labels<'a>(&self, program: &'a Program, block: &Block) -> Vec<&'a str> {
+        let mut labels: Vec<(usize, &str)> = program
+            .labels()
+            .iter()
+            .filter(|(_, &index)| (block.start..block.end).contains(&index))
+            .map(|(name, &index)| (index, name.as_str()))
+            .collect();
+        labels.sort_unstable();
+        labels.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Describe the source lines a block covers, counting from 1.
+    fn lines(program: &Program, block: &Block) -> String {
+        let instructions = program.instructions();
+        let first = instructions[block.start].0 + 1;
+        let last = instructions[block.end - 1].0 + 1;
+        match first == last {
+            true => format!("line {first}"),
+            false => format!("lines {first}-{last}"),
+        }
+    }
+
+    /// Write a plain text listing of every block and where it can go.
+    pub fn listing(&self, program: &Program) -> String {
+        let mut listing = String::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            let _ = write!(listing, "block {index}, {}", Self::lines(program, block));
+            let labels = self.labels(program, block);
+            if !labels.is_empty() {
+                let _ = write!(listing, " ({})", labels.join(", "));
+            }
+            listing.push('\n');
+            for edge in &block.edges {
+                let target = match edge.target {
+                    Target::Block(to) => format!("block {to}"),
+                    Target::Exit => "exit".to_string(),
+                    Target::Dynamic => "anywhere".to_string(),
+                };
+                let _ = writeln!(listing, "    {} -> {target}", edge_name(edge));
+            }
+            if block.edges.is_empty() {
+                listing.push_str("    halts\n");
+            }
+        }
+        listing
+    }
+
+    /// Write the graph in Graphviz's DOT language, with a node for every block
+    /// showing its labels and lines, and separate nodes for exiting and dynamic jumps.
+    pub fn dot(&self, program: &Program) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+        let targets = || self.blocks.iter().flat_map(|block| &block.edges);
+        if targets().any(|edge| edge.target == Target::Exit) {
+            dot.push_str("    exit [shape=oval, label=\"exit\"];\n");
+        }
+        if targets().any(|edge| edge.target == Target::Dynamic) {
+            dot.push_str("    dynamic [shape=diamond, label=\"dynamic\"];\n");
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            // Label names can have any character but whitespace, so they need escaping
+            let names: Vec<String> = self
+                .labels(program, block)
+                .iter()
+                .map(|name| name.replace('\\', "\\\\").replace('"', "\\\""))
+                .collect();
+            let mut label = names.join("\\n");
+            if !label.is_empty() {
+                label.push_str("\\n");
+            }
+            label.push_str(&Self::lines(program, block));
+            let _ = writeln!(dot, "    b{index} [label=\"{label}\"];");
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            for edge in &block.edges {
+                let (target, style) = match edge.target {
+                    Target::Block(to) => (format!("b{to}"), ""),
+                    Target::Exit => ("exit".to_string(), ""),
+                    Target::Dynamic => ("dynamic".to_string(), ", style=dashed"),
+                };
+                let _ = writeln!(
+                    dot,
+                    "    b{index} -> {target} [label=\"{}\"{style}];",
+                    edge_name(edge),
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Name an edge by how it's taken.
+fn edge_name(edge: &Edge) -> &'static str {
+    match edge.kind {
+        None => "next",
+        Some(JumpKind::Jump) => "jump",
+        Some(JumpKind::Branch) => "branch",
+        Some(JumpKind::Goto) => "goto",
+        Some(JumpKind::Call) => "call",
+        Some(JumpKind::Return) => "return",
+    }
+}
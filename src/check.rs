@@ -0,0 +1,575 @@
+use crate::cfg::{Cfg, Target};
+use crate::observer::JumpKind;
+use crate::structures::*;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+/// An instruction that was found to fail before running the program.
+pub struct Warning {
+    /// The index of the instruction.
+    pub index: usize,
+    /// The line the instruction is on, counting from 0.
+    pub line: usize,
+    pub instruction: Instruction,
+    /// Whether the instruction fails every time it's reached, instead of only sometimes.
+    pub always: bool,
+    /// The error it fails with.
+    pub error: Error,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let fails = if self.always {
+            "always fails"
+        } else {
+            "may fail"
+        };
+        write!(f, "line {} {fails}: {}", self.line + 1, self.error)
+    }
+}
+
+/// Check a program for instructions that fail, without running it.
+/// Only instructions that can be reached are checked, and warnings are sorted by index.
+pub fn check(program: &Program) -> Vec<Warning> {
+    let cfg = Cfg::new(program.instructions());
+    let (shapes, escapes) = solve_shapes(program, &cfg);
+    let mut warnings = check_registers(program, &cfg, &escapes);
+    // Registers are taken first, so instructions that always fail on them can't fail otherwise
+    let failing: Vec<usize> = (warnings.iter())
+        .filter(|warning| warning.always)
+        .map(|warning| warning.index)
+        .collect();
+    warnings.extend(
+        check_shapes(program, &shapes)
+            .into_iter()
+            .filter(|warning| !failing.contains(&warning.index)),
+    );
+    // Sorting is stable, so empty registers come first, as they're checked first
+    warnings.sort_by_key(|warning| warning.index);
+    warnings
+}
+
+/// A state that can be tracked through a program, for every instruction.
+trait Domain: Clone + PartialEq {
+    /// Combine the states of two paths that meet.
+    fn join(&self, other: &Self) -> Self;
+
+    /// Apply the effects of an instruction, assuming it succeeds.
+    fn transfer(&mut self, instruction: Instruction);
+
+    /// Learn from which way a branch went, after its instruction was applied.
+    fn branched(&mut self, _taken: bool) {}
+}
+
+/// Join a state into one that may not have been reached yet, returning whether it changed.
+fn merge<S: Domain>(slot: &mut Option<S>, state: &S) -> bool {
+    let joined = match slot {
+        Some(old) => old.join(state),
+        None => state.clone(),
+    };
+    let changed = slot.as_ref() != Some(&joined);
+    *slot = Some(joined);
+    changed
+}
+
+/// Find the state before every instruction, starting from an entry state.
+/// Instructions that can't be reached have no state.
+///
+/// Returns go to right after a call when `to_calls` tells that the index they return to
+/// came from one, given the return's index and the state before it.
+/// Other returns, and gotos, may go to any instruction at all.
+fn solve<S: Domain>(
+    program: &Program,
+    cfg: &Cfg,
+    entry: S,
+    mut to_calls: impl FnMut(usize, &S) -> bool,
+) -> Vec<Option<S>> {
+    let instructions = program.instructions();
+    let blocks = cfg.blocks();
+    let mut entries: Vec<Option<S>> = vec![None; blocks.len()];
+    let mut states: Vec<Option<S>> = vec![None; instructions.len()];
+    if blocks.is_empty() {
+        return states;
+    }
+    entries[0] = Some(entry);
+    let return_sites: Vec<usize> = (0..instructions.len())
+        .filter(|&index| matches!(instructions[index].1, Instruction::Call(_)))
+        .filter_map(|index| cfg.block_of(index + 1))
+        .collect();
+    // What every goto, and return that may not go back to a call, may carry into any instruction
+    let mut anywhere: Option<S> = None;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (block, entry) in blocks.iter().zip(entries.clone()) {
+            let Some(mut state) = entry.or_else(|| anywhere.clone()) else {
+                continue;
+            };
+            for (index, &(_, instruction)) in
+                instructions[block.start..block.end].iter().enumerate()
+            {
+                if let Some(anywhere) = &anywhere {
+                    state = state.join(anywhere);
+                }
+                states[block.start + index] = Some(state.clone());
+                state.transfer(instruction);
+            }
+            let last = block.end - 1;
+            let branches = matches!(instructions[last].1, Instruction::Branch(_));
+            let returns_to_calls = states[last]
+                .as_ref()
+                .is_some_and(|before| to_calls(last, before));
+            for edge in &block.edges {
+                let mut state = state.clone();
+                if branches {
+                    state.branched(edge.kind == Some(JumpKind::Branch));
+                }
+                let exits = match (edge.kind, edge.target) {
+                    (_, Target::Block(to)) => vec![to],
+                    (Some(JumpKind::Return), Target::Dynamic) if returns_to_calls => {
+                        return_sites.clone()
+                    }
+                    (_, Target::Dynamic) => {
+                        changed |= merge(&mut anywhere, &state);
+                        continue;
+                    }
+                    (_, Target::Exit) => continue,
+                };
+                for to in exits {
+                    changed |= merge(&mut entries[to], &state);
+                }
+            }
+        }
+    }
+    states
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether a register holds a value.
+enum Fill {
+    Full,
+    Empty,
+    /// Full on some paths, and empty on others.
+    Maybe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether each register holds a value.
+struct Registers {
+    x: Fill,
+    y: Fill,
+    /// The register that the boolean on top of the stack says was read into,
+    /// right after `input?` or `read?`.
+    read: Option<Register>,
+}
+
+impl Registers {
+    const EMPTY: Self = Self {
+        x: Fill::Empty,
+        y: Fill::Empty,
+        read: None,
+    };
+
+    fn get(&mut self, register: Register) -> &mut Fill {
+        match register {
+            Register::X => &mut self.x,
+            Register::Y => &mut self.y,
+        }
+    }
+}
+
+/// Get the registers an instruction needs to hold values, in the order they're checked.
+fn required(instruction: Instruction) -> Vec<Register> {
+    use Instruction::*;
+    match instruction {
+        PushRegister(reg)
+        | Copy(reg)
+        | Swap(reg, _)
+        | Goto(reg)
+        | Negate(reg)
+        | Not(reg)
+        | Cast(_, reg)
+        | Reinterpret(_, reg)
+        | Output(reg)
+        | Write(reg) => vec![reg],
+        Compare(_) | Add | Subtract | Multiply | Divide | Modulo | And | Or | Xor | Shift
+        | Rotate => vec![Register::X, Register::Y],
+        _ => Vec::new(),
+    }
+}
+
+impl Domain for Registers {
+    fn join(&self, other: &Self) -> Self {
+        let join = |a, b| if a == b { a } else { Fill::Maybe };
+        Self {
+            x: join(self.x, other.x),
+            y: join(self.y, other.y),
+            read: if self.read == other.read {
+                self.read
+            } else {
+                None
+            },
+        }
+    }
+
+    fn transfer(&mut self, instruction: Instruction) {
+        use Instruction::*;
+        // Branches use what was read once they know which way they went
+        if !matches!(instruction, Branch(_)) {
+            self.read = None;
+        }
+        // Reaching the end means every register that was needed held a value
+        for register in required(instruction) {
+            *self.get(register) = Fill::Full;
+        }
+        match instruction {
+            // Taking values out of registers empties them
+            PushRegister(reg) | Goto(reg) | Output(reg) | Write(reg) | Drop(reg) => {
+                *self.get(reg) = Fill::Empty
+            }
+            Compare(_) | Add | Subtract | Multiply | Divide | Modulo | And | Or | Xor | Shift
+            | Rotate => *self = Self::EMPTY,
+            Pop(Some(reg)) | Length(reg) | Input(_, reg) | Read(_, reg) | Random(_, reg) => {
+                *self.get(reg) = Fill::Full
+            }
+            // The other register is filled with a copy
+            Copy(Register::X) => self.y = Fill::Full,
+            Copy(Register::Y) => self.x = Fill::Full,
+            // Registers are emptied when the input has ended
+            TryInput(_, reg) | TryRead(_, reg) => {
+                *self.get(reg) = Fill::Maybe;
+                self.read = Some(reg);
+            }
+            _ => {}
+        }
+    }
+
+    fn branched(&mut self, taken: bool) {
+        if let Some(register) = self.read.take() {
+            *self.get(register) = if taken { Fill::Full } else { Fill::Empty };
+        }
+    }
+}
+
+/// Find instructions that fail because a register they need is empty,
+/// given which returns may go anywhere instead of back to a call.
+fn check_registers(program: &Program, cfg: &Cfg, escapes: &[bool]) -> Vec<Warning> {
+    let states = solve(program, cfg, Registers::EMPTY, |index, _| !escapes[index]);
+    let mut warnings = Vec::new();
+    for (index, &(line, instruction)) in program.instructions().iter().enumerate() {
+        let Some(mut registers) = states[index] else {
+            continue;
+        };
+        let fills: Vec<(Register, Fill)> = required(instruction)
+            .into_iter()
+            .map(|register| (register, *registers.get(register)))
+            .filter(|&(_, fill)| fill != Fill::Full)
+            .collect();
+        // Only one register is reported, preferring one that's always empty,
+        // as that's the one that makes the instruction always fail
+        let empty = fills.iter().find(|&&(_, fill)| fill == Fill::Empty);
+        let Some(&(register, fill)) = empty.or(fills.first()) else {
+            continue;
+        };
+        warnings.push(Warning {
+            index,
+            line,
+            instruction,
+            always: fill == Fill::Empty,
+            error: Error::EmptyRegister(register),
+        });
+    }
+    warnings
+}
+
+/// How many values on top of the stack have their types tracked.
+const TRACKED: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The type of a value, telling apart the indices pushed by calls.
+pub enum ValueKind {
+    /// A value that a call didn't push.
+    Value(Type),
+    /// An index pushed by a call, which returning to goes back after the call.
+    ReturnIndex,
+}
+
+impl ValueKind {
+    pub fn get_type(self) -> Type {
+        match self {
+            ValueKind::Value(ty) => ty,
+            ValueKind::ReturnIndex => Type::Integer,
+        }
+    }
+}
+
+impl From<Type> for ValueKind {
+    fn from(ty: Type) -> Self {
+        ValueKind::Value(ty)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What's known about the values in the registers and on the stack before an instruction.
+/// Types that aren't known are `None`, which includes empty registers.
+pub struct Shape {
+    /// The type of the value in X.
+    pub x: Option<ValueKind>,
+    /// The type of the value in Y.
+    pub y: Option<ValueKind>,
+    /// The types of the values on top of the stack, from the top down.
+    /// Only values that are always there are included, up to a limit.
+    pub top: Vec<Option<ValueKind>>,
+    /// The least amount of values the stack may hold.
+    pub min_depth: usize,
+    /// The most values the stack may hold, if there's a limit.
+    pub max_depth: Option<usize>,
+}
+
+impl Shape {
+    const EMPTY: Self = Self {
+        x: None,
+        y: None,
+        top: Vec::new(),
+        min_depth: 0,
+        max_depth: Some(0),
+    };
+
+    fn register(&mut self, register: Register) -> &mut Option<ValueKind> {
+        match register {
+            Register::X => &mut self.x,
+            Register::Y => &mut self.y,
+        }
+    }
+
+    fn push(&mut self, ty: Option<ValueKind>) {
+        self.top.insert(0, ty);
+        self.top.truncate(TRACKED);
+        self.min_depth += 1;
+        self.max_depth = self.max_depth.map(|depth| depth + 1);
+    }
+
+    fn pop(&mut self) -> Option<ValueKind> {
+        self.min_depth = self.min_depth.saturating_sub(1);
+        self.max_depth = self.max_depth.map(|depth| depth.saturating_sub(1));
+        match self.top.is_empty() {
+            true => None,
+            false => self.top.remove(0),
+        }
+    }
+
+    /// Take the values out of both registers,
+    /// returning the type of either one, which an operation on both of them results in.
+    fn take_both(&mut self) -> Option<ValueKind> {
+        let ty = self.x.or(self.y).map(|kind| kind.get_type().into());
+        (self.x, self.y) = (None, None);
+        ty
+    }
+
+    /// Whether returning goes back after a call. That's assumed unless the index on top
+    /// of the stack is known to be one that a call didn't push, as loops that change the
+    /// depth of the stack lose track of what's on it.
+    fn returns_to_call(&self) -> bool {
+        !matches!(self.top.first(), Some(Some(ValueKind::Value(_))))
+    }
+}
+
+impl Domain for Shape {
+    fn join(&self, other: &Self) -> Self {
+        // An index that may not have been pushed by a call is still an integer
+        let same = |a: Option<ValueKind>, b: Option<ValueKind>| match (a, b) {
+            _ if a == b => a,
+            (Some(a), Some(b)) if a.get_type() == b.get_type() => Some(a.get_type().into()),
+            _ => None,
+        };
+        Self {
+            x: same(self.x, other.x),
+            y: same(self.y, other.y),
+            top: (self.top.iter().zip(&other.top))
+                .map(|(&a, &b)| same(a, b))
+                .collect(),
+            min_depth: self.min_depth.min(other.min_depth),
+            // Limits that differ are dropped, so loops that keep pushing are analyzed in time
+            max_depth: match self.max_depth == other.max_depth {
+                true => self.max_depth,
+                false => None,
+            },
+        }
+    }
+
+    fn transfer(&mut self, instruction: Instruction) {
+        use Instruction::*;
+        match instruction {
+            PushInteger(_) => self.push(Some(Type::Integer.into())),
+            PushFloat(_) => self.push(Some(Type::Float.into())),
+            PushBoolean(_) => self.push(Some(Type::Boolean.into())),
+            PushCharacter(_) => self.push(Some(Type::Character.into())),
+            Call(_) => self.push(Some(ValueKind::ReturnIndex)),
+            PushRegister(reg) => {
+                let ty = self.register(reg).take();
+                self.push(ty);
+            }
+            Pop(reg) => {
+                let ty = self.pop();
+                if let Some(reg) = reg {
+                    *self.register(reg) = ty;
+                }
+            }
+            Copy(Register::X) => self.y = self.x,
+            Copy(Register::Y) => self.x = self.y,
+            Length(reg) => *self.register(reg) = Some(Type::Integer.into()),
+            Swap(reg, index) => {
+                // Succeeding means the stack was deep enough
+                self.min_depth = self.min_depth.max(index + 1);
+                self.max_depth = self.max_depth.map(|depth| depth.max(index + 1));
+                match self.top.get_mut(index) {
+                    Some(ty) => std::mem::swap(
+                        ty,
+                        match reg {
+                            Register::X => &mut self.x,
+                            Register::Y => &mut self.y,
+                        },
+                    ),
+                    None => *self.register(reg) = None,
+                }
+            }
+            Branch(_) | Return => {
+                self.pop();
+            }
+            Goto(reg) | Output(reg) | Write(reg) | Drop(reg) => *self.register(reg) = None,
+            Compare(_) => {
+                self.take_both();
+                self.push(Some(Type::Boolean.into()));
+            }
+            Add | Subtract | Multiply | Divide | Modulo | And | Or | Xor => {
+                let ty = self.take_both();
+                self.push(ty);
+            }
+            Shift | Rotate => {
+                self.take_both();
+                self.push(Some(Type::Integer.into()));
+            }
+            // Changed indices no longer go back after a call
+            Negate(reg) | Not(reg) => {
+                let kind = self.register(reg);
+                *kind = kind.map(|kind| kind.get_type().into());
+            }
+            // Only values that already have the type push whether they were cast
+            Cast(ty, reg) | Reinterpret(ty, reg) => {
+                match self.register(reg).map(ValueKind::get_type) {
+                    Some(old) if old == ty => self.push(Some(Type::Boolean.into())),
+                    Some(_) => {}
+                    None => {
+                        self.max_depth = self.max_depth.map(|depth| depth + 1);
+                        self.top.clear();
+                    }
+                }
+                *self.register(reg) = Some(ty.into());
+            }
+            Input(ty, reg) | Read(ty, reg) | Random(ty, reg) => {
+                *self.register(reg) = Some(ty.into())
+            }
+            TryInput(ty, reg) | TryRead(ty, reg) => {
+                *self.register(reg) = Some(ty.into());
+                self.push(Some(Type::Boolean.into()));
+            }
+            Jump(_) | Break | Debug => {}
+        }
+    }
+}
+
+/// Find what's known about the registers and the stack before every instruction.
+/// Instructions that can't be reached have no shape.
+pub fn shapes(program: &Program) -> Vec<Option<Shape>> {
+    let cfg = Cfg::new(program.instructions());
+    solve_shapes(program, &cfg).0
+}
+
+/// Find the shapes before every instruction, and which returns may go anywhere.
+/// Once a return may, it keeps doing so, even if going anywhere loses track of the stack.
+fn solve_shapes(program: &Program, cfg: &Cfg) -> (Vec<Option<Shape>>, Vec<bool>) {
+    let mut escapes = vec![false; program.len()];
+    let shapes = solve(program, cfg, Shape::EMPTY, |index, shape| {
+        escapes[index] |= !shape.returns_to_call();
+        !escapes[index]
+    });
+    (shapes, escapes)
+}
+
+/// Find the error an instruction always fails with because of the types it's given.
+fn type_error(shape: &Shape, instruction: Instruction) -> Option<Error> {
+    use Instruction::*;
+    use Type::*;
+    let x = shape.x.map(ValueKind::get_type);
+    let y = shape.y.map(ValueKind::get_type);
+    let both = x.zip(y);
+    let mismatched = |valid: &[Type]| {
+        both.filter(|&(x, y)| x != y || !valid.contains(&x))
+            .map(|(x, y)| Error::MismatchedTypes(x, y))
+    };
+    let invalid = |ty: Option<Type>, valid: &[Type]| {
+        ty.filter(|ty| !valid.contains(ty)).map(Error::InvalidType)
+    };
+    let top = shape.top.first().copied().flatten();
+    let top = top.map(ValueKind::get_type);
+    let register = |reg| match reg {
+        Register::X => x,
+        Register::Y => y,
+    };
+    match instruction {
+        Add | Subtract | Multiply | Divide | Modulo => mismatched(&[Integer, Float]),
+        And | Or | Xor => mismatched(&[Integer, Boolean]),
+        Shift | Rotate => invalid(x, &[Integer]).or_else(|| invalid(y, &[Integer])),
+        Branch(_) => invalid(top, &[Boolean]),
+        Return => invalid(top, &[Integer]),
+        Goto(reg) => invalid(register(reg), &[Integer]),
+        Negate(reg) => invalid(register(reg), &[Integer, Float]),
+        Not(reg) => invalid(register(reg), &[Integer, Boolean]),
+        _ => None,
+    }
+}
+
+/// Find how deep the stack needs to be for an instruction.
+fn needed_depth(instruction: Instruction) -> usize {
+    use Instruction::*;
+    match instruction {
+        Pop(_) | Branch(_) | Return => 1,
+        Swap(_, index) => index + 1,
+        _ => 0,
+    }
+}
+
+/// Find instructions that fail because of the types they're given,
+/// or because the stack isn't deep enough for them.
+fn check_shapes(program: &Program, shapes: &[Option<Shape>]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (index, &(line, instruction)) in program.instructions().iter().enumerate() {
+        let Some(shape) = &shapes[index] else {
+            continue;
+        };
+        let mut warn = |always, error| {
+            warnings.push(Warning {
+                index,
+                line,
+                instruction,
+                always,
+                error,
+            })
+        };
+        let needed = needed_depth(instruction);
+        if shape.min_depth < needed {
+            // The index is the one the interpreter reports for the shallowest stack
+            let out_of_bounds = shape.min_depth as i64 - needed as i64;
+            let error = match instruction {
+                Instruction::Swap(..) => Error::StackOutOfBounds(out_of_bounds),
+                _ => Error::StackOutOfBounds(0),
+            };
+            warn(shape.max_depth.is_some_and(|depth| depth < needed), error);
+            continue;
+        }
+        if let Some(error) = type_error(shape, instruction) {
+            warn(true, error);
+        }
+    }
+    warnings
+}
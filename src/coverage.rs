@@ -0,0 +1,101 @@
+use crate::observer::{JumpKind, Observer};
+use crate::structures::*;
+use crate::trace::Trace;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An observer that counts which instructions were executed, and which ways branches went.
+pub struct Coverage {
+    hits: Vec<u64>,
+    /// How many times each branch was taken and not taken, by index.
+    branches: Vec<(u64, u64)>,
+    /// Whether the instruction being executed jumped.
+    jumped: bool,
+}
+
+impl Coverage {
+    /// Create a coverage collector for a program.
+    pub fn new(program: &Program) -> Self {
+        Self {
+            hits: vec![0; program.len()],
+            branches: vec![(0, 0); program.len()],
+            jumped: false,
+        }
+    }
+
+    /// Get how many times each instruction was executed, by index.
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// Get how many times the branch at an index was taken and not taken.
+    /// Branches that failed because of a non-boolean value count as neither.
+    pub fn branch(&self, index: usize) -> (u64, u64) {
+        self.branches.get(index).copied().unwrap_or_default()
+    }
+
+    /// Write the coverage of a program as an lcov tracefile for a source file.
+    /// Labels are written as functions, and every branch has a taken and a not taken path.
+    pub fn lcov(&self, program: &Program, source_file: &str) -> String {
+        let instructions = program.instructions();
+        let mut lcov = format!("TN:\nSF:{source_file}\n");
+        let mut labels: Vec<(&String, usize)> = program
+            .labels()
+            .iter()
+            .map(|(name, &index)| (name, index))
+            .filter(|&(_, index)| index < instructions.len())
+            .collect();
+        labels.sort_unstable_by_key(|&(name, index)| (index, name));
+        for &(name, index) in &labels {
+            let _ = writeln!(lcov, "FN:{},{name}", instructions[index].0 + 1);
+        }
+        for &(name, index) in &labels {
+            let _ = writeln!(lcov, "FNDA:{},{name}", self.hits[index]);
+        }
+        let functions_hit = labels
+            .iter()
+            .filter(|&&(_, index)| self.hits[index] > 0)
+            .count();
+        let _ = writeln!(lcov, "FNF:{}\nFNH:{functions_hit}", labels.len());
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (index, (line, instruction)) in instructions.iter().enumerate() {
+            if !matches!(instruction, Instruction::Branch(_)) {
+                continue;
+            }
+            let (taken, not_taken) = self.branches[index];
+            for (path, count) in [taken, not_taken].into_iter().enumerate() {
+                // Branches that never ran at all are written with a dash
+                let count = match self.hits[index] {
+                    0 => "-".to_string(),
+                    _ => count.to_string(),
+                };
+                let _ = writeln!(lcov, "BRDA:{},{index},{path},{count}", line + 1);
+            }
+            branches += 2;
+            branches_hit += (taken > 0) as usize + (not_taken > 0) as usize;
+        }
+        let _ = writeln!(lcov, "BRF:{branches}\nBRH:{branches_hit}");
+        for (index, (line, _)) in instructions.iter().enumerate() {
+            let _ = writeln!(lcov, "DA:{},{}", line + 1, self.hits[index]);
+        }
+        let lines_hit = self.hits.iter().filter(|&&hits| hits > 0).count();
+        let _ = writeln!(lcov, "LF:{}\nLH:{lines_hit}", instructions.len());
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+impl Observer for Coverage {
+    fn after(&mut self, trace: &Trace) {
+        self.hits[trace.index] += 1;
+        let jumped = std::mem::take(&mut self.jumped);
+        if matches!(trace.instruction, Instruction::Branch(_)) && trace.error.is_none() {
+            let (taken, not_taken) = &mut self.branches[trace.index];
+            *if jumped { taken } else { not_taken } += 1;
+        }
+    }
+
+    fn jumped(&mut self, kind: JumpKind, _: usize, _: usize) {
+        self.jumped = kind == JumpKind::Branch;
+    }
+}
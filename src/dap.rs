@@ -0,0 +1,500 @@
+use crate::io::MemoryIo;
+use crate::json::{read_message, write_message, Json};
+use crate::machine::Machine;
+use crate::observer::{JumpKind, Observer};
+use crate::profiler::START_REGION;
+use crate::structures::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/// The only thread a program has.
+const THREAD_ID: i64 = 1;
+/// The variables reference for the registers.
+const REGISTERS: i64 = 1;
+/// The variables reference for the stack.
+const STACK: i64 = 2;
+
+/// Keeps track of the call instructions that execution is currently inside of.
+#[derive(Debug, Default)]
+struct CallStack(Vec<usize>);
+
+impl Observer for CallStack {
+    fn jumped(&mut self, kind: JumpKind, from: usize, _: usize) {
+        match kind {
+            JumpKind::Call => self.0.push(from),
+            JumpKind::Return => {
+                self.0.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A program that was asked to be launched.
+struct Launch {
+    path: String,
+    program: Program,
+    /// The input given to the program, as it can't read from stdin.
+    input: String,
+    seed: Option<u64>,
+    max_steps: Option<u64>,
+    stop_on_entry: bool,
+}
+
+/// A server for the Debug Adapter Protocol, which lets editors debug a single program.
+///
+/// Programs are launched with a `program` path, and optionally some `input` text,
+/// a random `seed`, a `maxSteps` limit and `stopOnEntry`.
+/// Their output is sent as output events.
+/// Running is done in between requests, so a program stuck in a loop can't be paused,
+/// which is what `maxSteps` is for.
+pub struct DapServer<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    seq: i64,
+}
+
+impl<R: BufRead, W: Write> DapServer<R, W> {
+    /// Create a server reading requests from an input, and writing to an output.
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            seq: 0,
+        }
+    }
+
+    /// Send a message, filling in its sequence number.
+    fn send(&mut self, kind: &str, mut entries: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        entries.insert(0, ("seq", self.seq.into()));
+        entries.insert(1, ("type", kind.into()));
+        write_message(&mut self.output, &Json::object(entries))
+    }
+
+    /// Send a successful response to a request.
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        let (request_seq, command) = request_info(request);
+        self.send(
+            "response",
+            vec![
+                ("request_seq", request_seq),
+                ("success", true.into()),
+                ("command", command),
+                ("body", body),
+            ],
+        )
+    }
+
+    /// Send a response saying a request failed.
+    fn fail(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        let (request_seq, command) = request_info(request);
+        self.send(
+            "response",
+            vec![
+                ("request_seq", request_seq),
+                ("success", false.into()),
+                ("command", command),
+                ("message", message.into()),
+            ],
+        )
+    }
+
+    /// Send an event.
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+
+    /// Serve requests until the client disconnects, or the input ends.
+    pub fn serve(&mut self) -> io::Result<()> {
+        let mut launch: Option<Launch> = None;
+        let mut breakpoints = BTreeSet::new();
+        let mut configured = false;
+        while let Some(request) = read_message(&mut self.input)? {
+            let arguments = request.get("arguments").unwrap_or(&Json::Null);
+            match command(&request) {
+                "initialize" => self.respond(
+                    &request,
+                    Json::object([("supportsConfigurationDoneRequest", true.into())]),
+                )?,
+                "launch" => match load(arguments) {
+                    Ok(loaded) => {
+                        launch = Some(loaded);
+                        self.respond(&request, Json::Null)?;
+                        // Breakpoints can only be placed once the program is known
+                        self.event("initialized", Json::Null)?;
+                    }
+                    Err(message) => self.fail(&request, &message)?,
+                },
+                "setBreakpoints" => match &launch {
+                    Some(launch) => {
+                        let program = &launch.program;
+                        self.set_breakpoints(&request, program, &mut breakpoints)?
+                    }
+                    None => self.fail(&request, "No program was launched yet.")?,
+                },
+                "configurationDone" => {
+                    self.respond(&request, Json::Null)?;
+                    configured = true;
+                }
+                "threads" => self.respond(&request, threads())?,
+                "disconnect" | "terminate" => return self.respond(&request, Json::Null),
+                _ => self.unsupported(&request)?,
+            }
+            if configured {
+                if let Some(launch) = launch.take() {
+                    return self.session(launch, breakpoints);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Respond to a request that can't be handled.
+    fn unsupported(&mut self, request: &Json) -> io::Result<()> {
+        match command(request) {
+            // Nothing to configure, but editors always send these
+            "setExceptionBreakpoints" | "setFunctionBreakpoints" => self.respond(
+                request,
+                Json::object([("breakpoints", Json::Array(vec![]))]),
+            ),
+            command => {
+                let message = format!("Unsupported request `{command}`.");
+                self.fail(request, &message)
+            }
+        }
+    }
+
+    /// Replace all breakpoints with the lines in a request,
+    /// moving each one to the first instruction at or after it.
+    fn set_breakpoints(
+        &mut self,
+        request: &Json,
+        program: &Program,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> io::Result<()> {
+        let lines = request
+            .get("arguments")
+            .and_then(|arguments| arguments.get("breakpoints"))
+            .and_then(Json::as_array)
+            .unwrap_or_default();
+        breakpoints.clear();
+        let mut verified = Vec::new();
+        for breakpoint in lines {
+            let line = breakpoint.get("line").and_then(Json::as_i64).unwrap_or(0);
+            let index = usize::try_from(line.saturating_sub(1))
+                .ok()
+                .and_then(|line| program.index_at_line(line));
+            verified.push(match index {
+                Some(index) => {
+                    breakpoints.insert(index);
+                    Json::object([
+                        ("verified", true.into()),
+                        ("line", (program.instructions()[index].0 + 1).into()),
+                    ])
+                }
+                None => Json::object([
+                    ("verified", false.into()),
+                    (
+                        "message",
+                        "There are no instructions after this line.".into(),
+                    ),
+                ]),
+            });
+        }
+        self.respond(
+            request,
+            Json::object([("breakpoints", Json::Array(verified))]),
+        )
+    }
+
+    /// Debug a launched program, until the client disconnects.
+    fn session(&mut self, launch: Launch, mut breakpoints: BTreeSet<usize>) -> io::Result<()> {
+        let rng = match launch.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut interpreter = Interpreter::with_rng(rng).with_io(MemoryIo::new(launch.input));
+        interpreter.max_steps = launch.max_steps;
+        let program = &launch.program;
+        let mut session = Session {
+            machine: Machine::new(&mut interpreter, program),
+            calls: CallStack::default(),
+            path: &launch.path,
+            reported: false,
+        };
+        if launch.stop_on_entry {
+            self.event("stopped", stopped("entry"))?;
+        } else if breakpoints.contains(&session.machine.pc()) {
+            // Advancing always steps first, which would skip a breakpoint on the first instruction
+            self.event("stopped", stopped("breakpoint"))?;
+        } else {
+            let reason = session.advance(&breakpoints, |_| false);
+            self.report(&mut session, reason)?;
+        }
+        while let Some(request) = read_message(&mut self.input)? {
+            let arguments = request.get("arguments").unwrap_or(&Json::Null);
+            match command(&request) {
+                "threads" => self.respond(&request, threads())?,
+                "stackTrace" => self.respond(&request, session.stack_trace())?,
+                "scopes" => self.respond(&request, scopes())?,
+                "variables" => {
+                    let reference = arguments.get("variablesReference");
+                    let variables = session.variables(reference.and_then(Json::as_i64));
+                    self.respond(&request, Json::object([("variables", variables)]))?
+                }
+                "continue" => {
+                    let body = Json::object([("allThreadsContinued", true.into())]);
+                    self.respond(&request, body)?;
+                    let reason = session.advance(&breakpoints, |_| false);
+                    self.report(&mut session, reason)?;
+                }
+                "next" | "stepIn" | "stepOut" => {
+                    self.respond(&request, Json::Null)?;
+                    let depth = session.calls.0.len();
+                    // Stepping over a call runs until it returns, and stepping out until
+                    // the current call returns
+                    let reason = match command(&request) {
+                        "next" => session.advance(&breakpoints, |calls| calls.0.len() <= depth),
+                        "stepOut" => session.advance(&breakpoints, |calls| calls.0.len() < depth),
+                        _ => session.advance(&breakpoints, |_| true),
+                    };
+                    self.report(&mut session, reason)?;
+                }
+                "pause" => self.respond(&request, Json::Null)?,
+                "setBreakpoints" => self.set_breakpoints(&request, program, &mut breakpoints)?,
+                "disconnect" | "terminate" => return self.respond(&request, Json::Null),
+                _ => self.unsupported(&request)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Send everything the program wrote,
+    /// and then say why it stopped, or that it ended.
+    fn report<G: Rng>(&mut self, session: &mut Session<'_, G>, reason: &str) -> io::Result<()> {
+        let written = std::mem::take(&mut session.machine.interpreter.io.output);
+        if !written.is_empty() {
+            let output = String::from_utf8_lossy(&written).into_owned();
+            self.event(
+                "output",
+                Json::object([("category", "stdout".into()), ("output", output.into())]),
+            )?;
+        }
+        let error = match session.machine.outcome() {
+            None => return self.event("stopped", stopped(reason)),
+            Some(Outcome::Errored(err)) => Some(err.clone()),
+            Some(_) => None,
+        };
+        match error {
+            // Errors stop at the instruction that failed first, so it can be looked at
+            Some(err) if !session.reported => {
+                session.reported = true;
+                self.event(
+                    "output",
+                    Json::object([
+                        ("category", "stderr".into()),
+                        ("output", format!("Runtime error: {err}\n").into()),
+                    ]),
+                )?;
+                let mut body = stopped("exception");
+                if let Json::Object(entries) = &mut body {
+                    entries.push(("description".into(), err.error.to_string().into()));
+                }
+                self.event("stopped", body)
+            }
+            error => {
+                let code = error.map_or(0, |_| 1);
+                self.event("exited", Json::object([("exitCode", Json::Integer(code))]))?;
+                self.event("terminated", Json::Null)
+            }
+        }
+    }
+}
+
+/// A launched program being debugged.
+struct Session<'a, R: Rng> {
+    machine: Machine<'a, R, MemoryIo>,
+    calls: CallStack,
+    path: &'a str,
+    /// Whether the error the program stopped with was already reported.
+    reported: bool,
+}
+
+impl<R: Rng> Session<'_, R> {
+    /// Step until the machine stops, reaches a breakpoint, or a condition on the calls is met,
+    /// always executing at least one instruction. Returns why it stopped.
+    fn advance(
+        &mut self,
+        breakpoints: &BTreeSet<usize>,
+        done: impl Fn(&CallStack) -> bool,
+    ) -> &'static str {
+        loop {
+            if self.machine.step_with(&mut self.calls).is_stopped() {
+                return "step";
+            }
+            if breakpoints.contains(&self.machine.pc()) {
+                return "breakpoint";
+            }
+            if done(&self.calls) {
+                return "step";
+            }
+        }
+    }
+
+    /// Create a stack frame for an instruction.
+    fn frame(&self, id: usize, index: usize) -> Json {
+        let program = self.machine.program();
+        // Halted programs may be past their last instruction
+        let line = program
+            .get(index.min(program.len().saturating_sub(1)))
+            .map_or(0, |(line, _)| line);
+        let name = program.enclosing_label(index).unwrap_or(START_REGION);
+        let file = self.path.rsplit(['/', '\\']).next().unwrap_or(self.path);
+        Json::object([
+            ("id", id.into()),
+            ("name", name.into()),
+            ("line", (line + 1).into()),
+            ("column", 1usize.into()),
+            (
+                "source",
+                Json::object([("name", file.into()), ("path", self.path.into())]),
+            ),
+        ])
+    }
+
+    /// List the current instruction, and then every call that led to it.
+    fn stack_trace(&self) -> Json {
+        let mut frames = vec![self.frame(0, self.machine.pc())];
+        for (depth, &index) in self.calls.0.iter().rev().enumerate() {
+            frames.push(self.frame(depth + 1, index));
+        }
+        Json::object([
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", Json::Array(frames)),
+        ])
+    }
+
+    /// List the registers, or the stack from the top down.
+    fn variables(&self, reference: Option<i64>) -> Json {
+        let interpreter = &*self.machine.interpreter;
+        let variables = match reference {
+            Some(REGISTERS) => vec![
+                variable("X", interpreter.x.as_ref()),
+                variable("Y", interpreter.y.as_ref()),
+            ],
+            Some(STACK) => interpreter
+                .stack
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(slot, value)| variable(&slot.to_string(), Some(value)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Json::Array(variables)
+    }
+}
+
+/// Get the sequence number and command of a request.
+fn request_info(request: &Json) -> (Json, Json) {
+    (
+        request.get("seq").cloned().unwrap_or(Json::Null),
+        command(request).into(),
+    )
+}
+
+/// Get the command of a request.
+fn command(request: &Json) -> &str {
+    request
+        .get("command")
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+}
+
+/// Read the program a launch request asks for.
+fn load(arguments: &Json) -> Result<Launch, String> {
+    let Some(path) = arguments.get("program").and_then(Json::as_str) else {
+        return Err("Expected a `program` to launch.".to_string());
+    };
+    let source =
+        std::fs::read_to_string(path).map_err(|err| format!("Failed to read `{path}`: {err}"))?;
+    let program = Program::parse_all(&source).map_err(|errors| {
+        let rendered: Vec<String> = errors.iter().map(|err| err.render(&source)).collect();
+        format!("Failed to parse program:\n{}", rendered.join("\n"))
+    })?;
+    let number = |name| {
+        arguments
+            .get(name)
+            .and_then(Json::as_i64)
+            .and_then(|n| u64::try_from(n).ok())
+    };
+    Ok(Launch {
+        path: path.to_string(),
+        program,
+        input: arguments
+            .get("input")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        seed: number("seed"),
+        max_steps: number("maxSteps"),
+        stop_on_entry: arguments
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false),
+    })
+}
+
+/// The body of a response listing the only thread.
+fn threads() -> Json {
+    Json::object([(
+        "threads",
+        Json::Array(vec![Json::object([
+            ("id", THREAD_ID.into()),
+            ("name", "main".into()),
+        ])]),
+    )])
+}
+
+/// The body of a response listing the registers and the stack as scopes.
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64| {
+        Json::object([
+            ("name", name.into()),
+            ("variablesReference", reference.into()),
+            ("expensive", false.into()),
+        ])
+    };
+    Json::object([(
+        "scopes",
+        Json::Array(vec![scope("Registers", REGISTERS), scope("Stack", STACK)]),
+    )])
+}
+
+/// The body of an event saying the program stopped.
+fn stopped(reason: &str) -> Json {
+    Json::object([
+        ("reason", reason.into()),
+        ("threadId", THREAD_ID.into()),
+        ("allThreadsStopped", true.into()),
+    ])
+}
+
+/// Describe a register or a stack slot as a variable.
+fn variable(name: &str, value: Option<&Value>) -> Json {
+    let mut entries = vec![
+        ("name", name.into()),
+        (
+            "value",
+            value.map_or("empty".to_string(), Value::to_string).into(),
+        ),
+        ("variablesReference", 0i64.into()),
+    ];
+    if let Some(value) = value {
+        entries.push(("type", value.get_type().to_string().into()));
+    }
+    Json::object(entries)
+}
@@ -0,0 +1,334 @@
+use crate::io::Io;
+use crate::machine::{Machine, StepResult};
+use crate::recorder::{PartialState, Recorder, Watch};
+use crate::structures::*;
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+const HELP: &str = "\
+Commands:
+	step [N], s         Executes the next N instructions, or just one.
+	continue, c         Runs until a breakpoint is hit or the program stops.
+	break <at>, b       Sets a breakpoint at a label or a line number.
+	delete [at], d      Removes a breakpoint, or all of them.
+	breakpoints         Lists all breakpoints.
+	where, w            Prints the current instruction.
+	print [what], p     Prints X, Y, a stack slot (counting from the top), or everything.
+	set <what> <value>  Sets X, Y or a stack slot. Values are written like in push,
+	                    like `set X integer 5`, and `set X empty` empties a register.
+	jump <at>           Moves execution to a label or a line number.
+	back [N]            Steps backwards through the last N instructions, or just one.
+	reverse, rc         Runs backwards until a breakpoint is hit or the start is reached.
+	last <what>         Runs backwards to right before X, Y or a stack slot last changed.
+	replay <N>          Moves to after the Nth recorded instruction, backwards or forwards.
+	history             Prints how many instructions were recorded.
+	help, h             Prints this message.
+	quit, q             Stops debugging.
+An empty line repeats the last command.";
+
+/// A step debugger for a machine, driven by textual commands.
+pub struct Debugger<'a, R: Rng, I: Io> {
+    /// The machine being debugged.
+    pub machine: Machine<'a, R, I>,
+    /// Records every step, so execution can go backwards.
+    pub recorder: Recorder,
+    source: &'a str,
+    /// Instruction indices to stop at.
+    breakpoints: BTreeSet<usize>,
+    last_command: String,
+}
+
+impl<'a, R: Rng, I: Io> Debugger<'a, R, I> {
+    /// Create a debugger for a machine, given the source code of its program.
+    pub fn new(machine: Machine<'a, R, I>, source: &'a str) -> Self {
+        Self {
+            machine,
+            source,
+            recorder: Recorder::new(),
+            breakpoints: BTreeSet::new(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Get the instruction indices that execution stops at.
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Find the instruction index for a label, or the first instruction at or after a line.
+    /// Lines are counted from 1.
+    pub fn locate(&self, at: &str) -> Option<usize> {
+        let program = self.machine.program();
+        if let Some(index) = program.label(at) {
+            return Some(index);
+        }
+        let line = usize::from_str(at).ok()?.checked_sub(1)?;
+        program.index_at_line(line)
+    }
+
+    /// Step until the machine stops or reaches a breakpoint,
+    /// always executing at least one instruction.
+    pub fn resume(&mut self) -> StepResult {
+        loop {
+            let result = self.recorder.step(&mut self.machine);
+            if result.is_stopped() || self.breakpoints.contains(&self.machine.pc()) {
+                return result;
+            }
+        }
+    }
+
+    /// Step backwards until a breakpoint is reached or there's nothing left to undo,
+    /// always undoing at least one instruction.
+    pub fn reverse(&mut self) {
+        while self.recorder.step_back(&mut self.machine) {
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return;
+            }
+        }
+    }
+
+    /// Print where the machine currently is.
+    pub fn print_location(&self, out: &mut impl Write) -> io::Result<()> {
+        if let Some(outcome) = self.machine.outcome() {
+            return match outcome {
+                Outcome::Halted => writeln!(out, "The program halted."),
+                Outcome::Break => writeln!(out, "The program hit a break."),
+                Outcome::Errored(err) => writeln!(out, "Runtime error: {err}"),
+            };
+        }
+        let Some((line, _)) = self.machine.current() else {
+            return writeln!(out, "The program has no more instructions.");
+        };
+        let text = self.source.lines().nth(line).unwrap_or_default().trim();
+        let marker = if self.breakpoints.contains(&self.machine.pc()) {
+            '*'
+        } else {
+            ' '
+        };
+        writeln!(
+            out,
+            "{marker} line {} (instruction #{}): {text}",
+            line + 1,
+            self.machine.pc()
+        )
+    }
+
+    /// Print the registers and the stack, with the top of the stack first.
+    fn print_state(&self, out: &mut impl Write) -> io::Result<()> {
+        let interpreter = &*self.machine.interpreter;
+        writeln!(out, "X: {}", describe(&interpreter.x))?;
+        writeln!(out, "Y: {}", describe(&interpreter.y))?;
+        writeln!(out, "Stack ({} values):", interpreter.stack.len())?;
+        for (slot, value) in interpreter.stack.iter().rev().enumerate() {
+            writeln!(out, "\t{slot}: {}", describe(&Some(value.clone())))?;
+        }
+        Ok(())
+    }
+
+    /// Get the stack index of a slot, counting from the top.
+    fn slot(&self, what: &str) -> Option<usize> {
+        let slot = usize::from_str(what).ok()?;
+        self.machine.interpreter.stack.len().checked_sub(slot + 1)
+    }
+
+    /// Run a single command, writing its results.
+    /// Returns false if debugging should stop.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command.clone_from(&line);
+        let mut words = line.split_ascii_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let argument = words.next();
+        match (command, argument) {
+            ("step" | "s", count) => {
+                let Some(count) = count.map_or(Some(1), |count| usize::from_str(count).ok()) else {
+                    writeln!(out, "Expected an amount of instructions to step.")?;
+                    return Ok(true);
+                };
+                for _ in 0..count {
+                    if self.recorder.step(&mut self.machine).is_stopped() {
+                        break;
+                    }
+                }
+                self.print_location(out)?;
+            }
+            ("continue" | "c", _) => {
+                self.resume();
+                self.print_location(out)?;
+            }
+            ("break" | "b", Some(at)) => match self.locate(at) {
+                Some(index) => {
+                    self.breakpoints.insert(index);
+                    writeln!(out, "Breakpoint set at instruction #{index}.")?;
+                }
+                None => writeln!(out, "Could not find label or line `{at}`.")?,
+            },
+            ("delete" | "d", None) => {
+                self.breakpoints.clear();
+                writeln!(out, "Deleted all breakpoints.")?;
+            }
+            ("delete" | "d", Some(at)) => match self.locate(at) {
+                Some(index) if self.breakpoints.remove(&index) => {
+                    writeln!(out, "Deleted the breakpoint at instruction #{index}.")?
+                }
+                _ => writeln!(out, "There's no breakpoint at `{at}`.")?,
+            },
+            ("breakpoints", _) => {
+                for &index in &self.breakpoints {
+                    match self.machine.program().get(index) {
+                        Some((line, _)) => {
+                            writeln!(out, "Instruction #{index}, at line {}", line + 1)?
+                        }
+                        None => writeln!(out, "Instruction #{index}, at the end")?,
+                    }
+                }
+            }
+            ("where" | "w", _) => self.print_location(out)?,
+            ("print" | "p", None) => self.print_state(out)?,
+            ("print" | "p", Some(what)) => {
+                let value = match what {
+                    "X" => Some(self.machine.interpreter.x.clone()),
+                    "Y" => Some(self.machine.interpreter.y.clone()),
+                    _ => self
+                        .slot(what)
+                        .map(|index| Some(self.machine.interpreter.stack[index].clone())),
+                };
+                match value {
+                    Some(value) => writeln!(out, "{what}: {}", describe(&value))?,
+                    None => writeln!(out, "There's no register or stack slot `{what}`.")?,
+                }
+            }
+            ("set", Some(what)) => {
+                let rest: Vec<&str> = words.collect();
+                let value = match rest[..] {
+                    ["empty"] => None,
+                    [ty, value] => {
+                        let instruction =
+                            Instruction::parse(&format!(" push {ty} {value}"), &HashMap::new());
+                        match instruction {
+                            Ok(Some(Instruction::PushInteger(i))) => Some(i.into()),
+                            Ok(Some(Instruction::PushFloat(f))) => Some(f.into()),
+                            Ok(Some(Instruction::PushBoolean(b))) => Some(b.into()),
+                            Ok(Some(Instruction::PushCharacter(c))) => Some(c.into()),
+                            _ => {
+                                writeln!(out, "Could not parse `{ty} {value}` as a value.")?;
+                                return Ok(true);
+                            }
+                        }
+                    }
+                    _ => {
+                        writeln!(out, "Expected a type and a value, or `empty`.")?;
+                        return Ok(true);
+                    }
+                };
+                self.set(what, value, out)?;
+            }
+            ("jump", Some(at)) => match self.locate(at) {
+                Some(index) => {
+                    self.machine.set_pc(index);
+                    self.recorder.forget_future();
+                    self.print_location(out)?;
+                }
+                None => writeln!(out, "Could not find label or line `{at}`.")?,
+            },
+            ("back", count) => {
+                let Some(count) = count.map_or(Some(1), |count| usize::from_str(count).ok()) else {
+                    writeln!(out, "Expected an amount of instructions to step back.")?;
+                    return Ok(true);
+                };
+                for _ in 0..count {
+                    if !self.recorder.step_back(&mut self.machine) {
+                        writeln!(out, "Reached the start of the recording.")?;
+                        break;
+                    }
+                }
+                self.print_location(out)?;
+            }
+            ("reverse" | "rc", _) => {
+                self.reverse();
+                self.print_location(out)?;
+            }
+            ("last", Some(what)) => {
+                let watch = match what {
+                    "X" => Some(Watch::X),
+                    "Y" => Some(Watch::Y),
+                    _ => self.slot(what).map(Watch::Stack),
+                };
+                let Some(watch) = watch else {
+                    writeln!(out, "There's no register or stack slot `{what}`.")?;
+                    return Ok(true);
+                };
+                match self.recorder.last_change(&mut self.machine, watch) {
+                    Some(_) => {
+                        let delta = &self.recorder.deltas()[self.recorder.position()];
+                        let value = |state: &PartialState| match watch {
+                            Watch::X => state.x.clone(),
+                            Watch::Y => state.y.clone(),
+                            Watch::Stack(index) => {
+                                state.stack.get(index - delta.stack_base).cloned()
+                            }
+                        };
+                        writeln!(
+                            out,
+                            "{what} changes from {} to {} here.",
+                            describe(&value(&delta.before)),
+                            describe(&value(&delta.after))
+                        )?;
+                        self.print_location(out)?;
+                    }
+                    None => writeln!(out, "{what} never changed in the recording.")?,
+                }
+            }
+            ("replay", Some(position)) => match usize::from_str(position) {
+                Ok(position) => {
+                    self.recorder.seek(&mut self.machine, position);
+                    self.print_location(out)?;
+                }
+                Err(_) => writeln!(out, "Expected a position in the recording.")?,
+            },
+            ("history", _) => writeln!(
+                out,
+                "At instruction {} of {} recorded.",
+                self.recorder.position(),
+                self.recorder.deltas().len()
+            )?,
+            ("help" | "h", _) => writeln!(out, "{HELP}")?,
+            ("quit" | "q", _) => return Ok(false),
+            _ => writeln!(out, "Unknown command `{line}`. Try `help`.")?,
+        }
+        Ok(true)
+    }
+
+    /// Set a register or a stack slot, counting from the top, to a value.
+    fn set(&mut self, what: &str, value: Option<Value>, out: &mut impl Write) -> io::Result<()> {
+        let index = self.slot(what);
+        let interpreter = &mut *self.machine.interpreter;
+        match what {
+            "X" => interpreter.x = value,
+            "Y" => interpreter.y = value,
+            _ => match (index, value) {
+                (Some(index), Some(value)) => interpreter.stack[index] = value,
+                (Some(_), None) => return writeln!(out, "Stack slots can't be empty."),
+                (None, _) => return writeln!(out, "There's no register or stack slot `{what}`."),
+            },
+        }
+        // The recording can't be replayed past a state it didn't make
+        self.recorder.forget_future();
+        writeln!(out, "Set {what}.")
+    }
+}
+
+/// Describe a value alongside its type, or say that it's empty.
+fn describe(value: &Option<Value>) -> String {
+    match value {
+        Some(value) => format!("{value} ({})", value.get_type()),
+        None => "empty".to_string(),
+    }
+}
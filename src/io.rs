@@ -0,0 +1,141 @@
+use crate::structures::Type;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// Where an interpreter reads its input from, and writes its output to.
+pub trait Io {
+    /// Read some input into a buffer, returning how many bytes were read.
+    /// Reading 0 bytes means that the input has ended.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    /// Write all of the bytes as output.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()>;
+    /// Make sure all written output reaches its destination.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    /// Called before reading a line of text for an input instruction,
+    /// with the type being asked for and how many lines were rejected so far.
+    /// Does nothing by default, but interactive consoles may want to show a prompt.
+    fn prompt(&mut self, ty: Type, rejected: usize) -> io::Result<()> {
+        let _ = (ty, rejected);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Reads from stdin, and writes to stdout.
+pub struct Stdio;
+
+impl Io for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        io::stdout().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Reads from a buffer in memory, and writes into another.
+pub struct MemoryIo {
+    input: VecDeque<u8>,
+    /// Everything that has been written so far.
+    pub output: Vec<u8>,
+}
+
+impl MemoryIo {
+    /// Create an in-memory console that has the given input.
+    pub fn new(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            input: input.into().into(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Get the input that hasn't been read yet.
+    pub fn remaining_input(&self) -> &VecDeque<u8> {
+        &self.input
+    }
+}
+
+impl Io for MemoryIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// Gives input one line at a time from a script, like someone typing it in,
+/// and writes into a buffer in memory.
+pub struct ScriptedIo {
+    lines: VecDeque<String>,
+    /// The rest of the line currently being read.
+    current: VecDeque<u8>,
+    /// Everything that has been written so far.
+    pub output: Vec<u8>,
+}
+
+impl ScriptedIo {
+    /// Create a scripted console that gives out the given lines.
+    pub fn new<S: Into<String>>(lines: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            lines: lines.into_iter().map(Into::into).collect(),
+            current: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Add a line to the end of the script.
+    pub fn push_line(&mut self, line: impl Into<String>) {
+        self.lines.push_back(line.into());
+    }
+
+    /// Get the amount of lines that haven't started being read yet.
+    pub fn remaining_lines(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+impl Io for ScriptedIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.current.is_empty() {
+            let Some(line) = self.lines.pop_front() else {
+                return Ok(0);
+            };
+            self.current.extend(line.bytes());
+            self.current.push_back(b'\n');
+        }
+        // Never give out more than one line at once
+        self.current.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Any pair of a reader and a writer can be used for input and output.
+impl<I: Read, O: Write> Io for (I, O) {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.1.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.1.flush()
+    }
+}
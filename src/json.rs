@@ -0,0 +1,427 @@
+use crate::structures::Value;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A JSON value, for machine-readable input and output.
+pub enum Json {
+    Null,
+    Boolean(bool),
+    /// Kept apart from floats, so that large integers are written exactly.
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Keys are kept in the order they were added.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Create an object from its keys and values.
+    pub fn object<K: Into<String>>(entries: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Json::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// Get the value of a key, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Get this as a string, if it is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get this as an integer, if it is one.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Get this as a boolean, if it is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Get the values of this array, if it is one.
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A reason why text couldn't be parsed as JSON.
+pub struct JsonError {
+    /// The byte offset that couldn't be parsed.
+    pub position: usize,
+    /// What was expected there.
+    pub expected: &'static str,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} at byte {}", self.expected, self.position)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+/// A parser over the bytes of some JSON text.
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &'static str) -> JsonError {
+        JsonError {
+            position: self.position,
+            expected,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    /// Skip over some exact text, if it's next.
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.text[self.position..].starts_with(text);
+        if found {
+            self.position += text.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(b'{') => {
+                self.position += 1;
+                let mut entries = Vec::new();
+                self.skip_whitespace();
+                if !self.eat("}") {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.skip_whitespace();
+                        if !self.eat(":") {
+                            return Err(self.error("`:`"));
+                        }
+                        entries.push((key, self.value()?));
+                        if self.eat("}") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return Err(self.error("`,` or `}`"));
+                        }
+                    }
+                }
+                Json::Object(entries)
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if !self.eat("]") {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return Err(self.error("`,` or `]`"));
+                        }
+                    }
+                }
+                Json::Array(values)
+            }
+            Some(b'"') => Json::String(self.string()?),
+            _ if self.eat("null") => Json::Null,
+            _ if self.eat("true") => Json::Boolean(true),
+            _ if self.eat("false") => Json::Boolean(false),
+            _ => self.number()?,
+        };
+        self.skip_whitespace();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let rest = &self.text[self.position..];
+        let length = rest
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(rest.len());
+        let number = &rest[..length];
+        let value = match i64::from_str(number) {
+            Ok(i) => Json::Integer(i),
+            // Numbers too big to be integers are kept as floats
+            Err(_) => match f64::from_str(number) {
+                Ok(d) if !number.is_empty() && number.ends_with(|c: char| c.is_ascii_digit()) => {
+                    Json::Float(d)
+                }
+                _ => return Err(self.error("value")),
+            },
+        };
+        self.position += length;
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        if !self.eat("\"") {
+            return Err(self.error("string"));
+        }
+        let mut string = String::new();
+        loop {
+            let rest = &self.text[self.position..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                self.position = self.text.len();
+                return Err(self.error("`\"`"));
+            };
+            string.push_str(&rest[..end]);
+            self.position += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(string);
+            }
+            let escaped = match self.peek() {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => {
+                    self.position += 1;
+                    let high = self.hex()?;
+                    // Characters outside of the basic plane are written as two halves
+                    let code = if (0xD800..0xDC00).contains(&high) && self.eat("\\u") {
+                        let low = self.hex()?;
+                        0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                    } else {
+                        high
+                    };
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    continue;
+                }
+                _ => return Err(self.error("escape sequence")),
+            };
+            string.push(escaped);
+            self.position += 1;
+        }
+    }
+
+    /// Parse the 4 hexadecimal digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .text
+            .get(self.position..self.position + 4)
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("4 hexadecimal digits"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
+
+impl FromStr for Json {
+    type Err = JsonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            text: s,
+            position: 0,
+        };
+        let value = parser.value()?;
+        if parser.position < s.len() {
+            return Err(parser.error("end of text"));
+        }
+        Ok(value)
+    }
+}
+
+/// Read a JSON message that's framed with a `Content-Length` header,
+/// like in the Debug Adapter and Language Server protocols.
+/// Returns `None` if the input ended before a message started.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    let mut started = false;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            if started {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(None);
+        }
+        started = true;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = usize::from_str(value.trim()).ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message has no Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body =
+        String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Json::from_str(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Write a JSON message, framed with a `Content-Length` header.
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Boolean(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Self {
+        Json::Integer(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Integer(value as i64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Json::Integer(value as i64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+/// Values are written as an object with their type and value,
+/// like `{"type":"character","value":"H"}`.
+impl From<&Value> for Json {
+    fn from(value: &Value) -> Self {
+        let inner = match value {
+            Value::Integer(i) => Json::Integer(*i),
+            Value::Float(d) => Json::Float(*d),
+            Value::Boolean(b) => Json::Boolean(*b),
+            Value::Character(c) => Json::String((*c as char).to_string()),
+        };
+        Json::object([
+            ("type", Json::String(value.get_type().to_string())),
+            ("value", inner),
+        ])
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+/// Write a string with JSON escapes.
+fn write_string(f: &mut Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Writes compact JSON, all on one line.
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(b) => write!(f, "{b}"),
+            Json::Integer(i) => write!(f, "{i}"),
+            Json::Float(d) if d.is_finite() => write!(f, "{d:?}"),
+            // JSON has no infinities or NaN, so they're written as strings
+            Json::Float(d) => write_string(f, &d.to_string()),
+            Json::String(s) => write_string(f, s),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
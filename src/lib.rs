@@ -1,14 +1,39 @@
 extern crate core;
 
+pub(crate) mod cfg;
+pub(crate) mod check;
+pub(crate) mod coverage;
+pub(crate) mod dap;
+pub(crate) mod debugger;
+pub(crate) mod io;
+pub mod json;
+pub(crate) mod lsp;
+pub(crate) mod machine;
+pub(crate) mod observer;
 pub(crate) mod parser;
+pub(crate) mod profiler;
+pub(crate) mod recorder;
 pub(crate) mod structures;
+pub(crate) mod trace;
 
 use rand::Rng;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::ErrorKind;
 use std::str::FromStr;
 
+pub use cfg::*;
+pub use check::*;
+pub use coverage::*;
+pub use dap::*;
+pub use debugger::*;
+pub use io::*;
+pub use lsp::*;
+pub use machine::*;
+pub use observer::*;
 pub use parser::parse_file;
+pub use profiler::*;
+pub use recorder::*;
 pub use structures::*;
+pub use trace::*;
 
 // Macros for ergonomics inside the function
 macro_rules! take {
@@ -45,9 +70,9 @@ macro_rules! math {
         let rhs = take!($self.Y);
         match (lhs, rhs) {
             (Value::Integer(l), Value::Integer(r)) =>
-                $self.stack.push((l.$for_int(r)).into()),
+                $self.push(l.$for_int(r))?,
             (Value::Float(l), Value::Float(r)) =>
-                $self.stack.push((l $for_float r).into()),
+                $self.push(l $for_float r)?,
             (l, r) =>
                 return Err(Error::MismatchedTypes(l.get_type(), r.get_type()))
         }
@@ -60,9 +85,9 @@ macro_rules! logic {
         let rhs = take!($self.Y);
         match (lhs, rhs) {
             (Value::Integer(l), Value::Integer(r)) =>
-                $self.stack.push((l $operand r).into()),
+                $self.push(l $operand r)?,
             (Value::Boolean(l), Value::Boolean(r)) =>
-                $self.stack.push((l $operand r).into()),
+                $self.push(l $operand r)?,
             (l, r) =>
                 return Err(Error::MismatchedTypes(l.get_type(), r.get_type()))
         }
@@ -70,7 +95,7 @@ macro_rules! logic {
 }
 
 // Interpreter implementation
-impl<R: Rng> Interpreter<R> {
+impl<R: Rng, I: Io> Interpreter<R, I> {
     fn register(&mut self, register: Register) -> &mut Option<Value> {
         match register {
             Register::X => &mut self.x,
@@ -78,35 +103,154 @@ impl<R: Rng> Interpreter<R> {
         }
     }
 
+    fn push(&mut self, value: impl Into<Value>) -> Result<(), Error> {
+        if let Some(limit) = self.max_stack {
+            if self.stack.len() >= limit {
+                return Err(Error::StackOverflow(limit));
+            }
+        }
+        self.stack.push(value.into());
+        Ok(())
+    }
+
     fn pop(&mut self) -> Result<Value, Error> {
         self.stack.pop().ok_or(Error::StackOutOfBounds(0))
     }
 
+    /// Read more of the input into the input buffer.
+    /// Returns false if the input has ended.
+    fn fill_buffer(&mut self) -> Result<bool, Error> {
+        let mut chunk = [0; 1024];
+        loop {
+            match self.io.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(length) => {
+                    self.input_buffer.extend_from_slice(&chunk[..length]);
+                    return Ok(true);
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Take a line from the input, without its line ending.
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut searched = 0;
+        let end = loop {
+            if let Some(position) = self.input_buffer[searched..].iter().position(|&b| b == b'\n') {
+                break searched + position + 1;
+            }
+            searched = self.input_buffer.len();
+            if !self.fill_buffer()? {
+                // The last line may not have a line ending
+                if self.input_buffer.is_empty() {
+                    return Err(Error::EndOfInput);
+                }
+                break self.input_buffer.len();
+            }
+        };
+        let line: Vec<u8> = self.input_buffer.drain(..end).collect();
+        Ok(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Take an exact amount of bytes from the input.
+    /// If the input ends before then, the bytes are left in the buffer.
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        while self.input_buffer.len() < N {
+            if !self.fill_buffer()? {
+                return Err(Error::EndOfInput);
+            }
+        }
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.input_buffer[..N]);
+        self.input_buffer.drain(..N);
+        Ok(bytes)
+    }
+
+    /// Grab a value from the input as text,
+    /// re-reading lines until one can be parsed as the given type,
+    /// as long as the retry policy allows it.
+    fn input_value(&mut self, ty: Type) -> Result<Value, Error> {
+        let mut rejected = 0;
+        loop {
+            self.io
+                .prompt(ty, rejected)
+                .map_err(|err| Error::WriteFailed(err.kind()))?;
+            let line = self.read_line()?;
+            let trimmed = line.trim();
+            // Mapping with Value::from works, .map can take more than closures
+            let parsed = match ty {
+                Type::Integer => i64::from_str(trimmed).ok().map(Value::from),
+                Type::Float => f64::from_str(trimmed).ok().map(Value::from),
+                Type::Boolean => bool::from_str(trimmed).ok().map(Value::from),
+                // Grab the first byte of the string and treat that as the character
+                Type::Character => trimmed.bytes().next().map(Value::from),
+            };
+            if let Some(value) = parsed {
+                return Ok(value);
+            }
+            let retry = match self.input_retry {
+                InputRetry::Forever => true,
+                InputRetry::Times(times) => rejected < times,
+                InputRetry::Never => false,
+            };
+            if !retry {
+                return Err(Error::InvalidInput(ty, line));
+            }
+            rejected += 1;
+        }
+    }
+
+    /// Grab a value from the input as bytes.
+    fn read_value(&mut self, ty: Type) -> Result<Value, Error> {
+        Ok(match ty {
+            Type::Integer => i64::from_be_bytes(self.read_bytes()?).into(),
+            Type::Float => f64::from_be_bytes(self.read_bytes()?).into(),
+            Type::Boolean => (self.read_bytes::<1>()?[0] != 0).into(),
+            Type::Character => self.read_bytes::<1>()?[0].into(),
+        })
+    }
+
+    /// Put a value that may have hit the end of input into a register,
+    /// pushing whether there was a value.
+    fn try_store(&mut self, register: Register, value: Result<Value, Error>) -> Result<(), Error> {
+        match value {
+            Ok(value) => {
+                *self.register(register) = Some(value);
+                self.push(true)
+            }
+            Err(Error::EndOfInput) => {
+                *self.register(register) = None;
+                self.push(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// Execute an instruction in this interpreter.
-    /// If successful, may return an index in the program to jump to.
+    /// If successful, returns where execution should go next.
     /// Returns an error if execution failed.
     pub fn execute(
         &mut self,
         index: usize,
         instr: Instruction,
-        mut input: impl Read,
-        mut output: impl Write,
         line_number: Option<usize>
-    ) -> Result<Option<usize>, Error> {
+    ) -> Result<Flow, Error> {
         use Register::*;
         match instr {
             // Pushing stuff
-            Instruction::PushInteger(int) => self.stack.push(Value::Integer(int)),
-            Instruction::PushFloat(float) => self.stack.push(Value::Float(float)),
-            Instruction::PushBoolean(boolean) => self.stack.push(Value::Boolean(boolean)),
-            Instruction::PushCharacter(character) => self.stack.push(Value::Character(character)),
+            Instruction::PushInteger(int) => self.push(Value::Integer(int))?,
+            Instruction::PushFloat(float) => self.push(Value::Float(float))?,
+            Instruction::PushBoolean(boolean) => self.push(Value::Boolean(boolean))?,
+            Instruction::PushCharacter(character) => self.push(Value::Character(character))?,
             Instruction::PushRegister(reg) => {
                 let value = take!(self.reg);
-                self.stack.push(value);
+                self.push(value)?;
             }
             Instruction::Pop(reg) => {
                 let value = self.pop()?;
-                let Some(reg) = reg else { return Ok(None) };
+                let Some(reg) = reg else { return Ok(Flow::Continue) };
                 let register = self.register(reg);
                 let _ = register.insert(value);
             }
@@ -139,30 +283,33 @@ impl<R: Rng> Interpreter<R> {
                 *self.register(reg) = Some((self.stack.len() as i64).into())
             }
             // Moving around the instruction pointer
-            Instruction::Jump(to) => return Ok(Some(to)),
+            Instruction::Jump(to) => return Ok(Flow::Jump(to)),
             Instruction::Branch(to) => {
                 if typed!(self.pop()? => Boolean) {
-                    return Ok(Some(to));
+                    return Ok(Flow::Jump(to));
                 }
             }
             Instruction::Goto(reg) => {
                 let index = typed!(take!(self.reg) => Integer);
                 if index < 0 {
-                    return Ok(Some(usize::MAX));
+                    return Ok(Flow::Halt);
                 }
-                return Ok(Some(index as usize));
+                return Ok(Flow::Jump(index as usize));
             }
             // Call and return
             Instruction::Call(to) => {
-                self.stack.push((index as i64).into());
-                return Ok(Some(to));
+                self.push(index as i64)?;
+                return Ok(Flow::Jump(to));
             }
             Instruction::Return => {
                 let popped = self.pop()?;
                 let Value::Integer(to) = popped else {
                     return Err(Error::InvalidType(popped.get_type()));
                 };
-                return Ok(Some(to as usize + 1));
+                if to < 0 {
+                    return Ok(Flow::Halt);
+                }
+                return Ok(Flow::Jump(to as usize + 1));
             }
             // Math!
             Instruction::Compare(kind) => {
@@ -181,7 +328,7 @@ impl<R: Rng> Interpreter<R> {
                 } else {
                     lhs != rhs
                 };
-                self.stack.push(result.into())
+                self.push(result)?
             }
             Instruction::Add => math!(self, wrapping_add, +),
             Instruction::Subtract => math!(self, wrapping_sub, -),
@@ -194,9 +341,9 @@ impl<R: Rng> Interpreter<R> {
                         if r == 0 {
                             return Err(Error::DivideByZero);
                         }
-                        self.stack.push(l.wrapping_div(r).into())
+                        self.push(l.wrapping_div(r))?
                     }
-                    (Value::Float(l), Value::Float(r)) => self.stack.push((l / r).into()),
+                    (Value::Float(l), Value::Float(r)) => self.push(l / r)?,
                     (l, r) => return Err(Error::MismatchedTypes(l.get_type(), r.get_type())),
                 }
             }
@@ -208,9 +355,9 @@ impl<R: Rng> Interpreter<R> {
                         if r == 0 {
                             return Err(Error::DivideByZero);
                         }
-                        self.stack.push(l.wrapping_rem(r).into())
+                        self.push(l.wrapping_rem(r))?
                     }
-                    (Value::Float(l), Value::Float(r)) => self.stack.push((l % r).into()),
+                    (Value::Float(l), Value::Float(r)) => self.push(l % r)?,
                     (l, r) => return Err(Error::MismatchedTypes(l.get_type(), r.get_type())),
                 }
             }
@@ -238,35 +385,29 @@ impl<R: Rng> Interpreter<R> {
                 let rhs = typed!(take!(self.Y) => Integer);
                 let direction = rhs < 0; // Left if below 0
                 let rhs = rhs.unsigned_abs() as u32;
-                self.stack.push(
-                    if direction {
-                        lhs.wrapping_shl(rhs)
-                    } else {
-                        // Logical bit shift
-                        (lhs as u64).wrapping_shr(rhs) as i64
-                    }
-                    .into(),
-                )
+                self.push(if direction {
+                    lhs.wrapping_shl(rhs)
+                } else {
+                    // Logical bit shift
+                    (lhs as u64).wrapping_shr(rhs) as i64
+                })?
             }
             Instruction::Rotate => {
                 let lhs = typed!(take!(self.X) => Integer);
                 let rhs = typed!(take!(self.Y) => Integer);
                 let direction = rhs < 0; // Left if below 0
                 let rhs = rhs.unsigned_abs() as u32;
-                self.stack.push(
-                    if direction {
-                        lhs.rotate_left(rhs)
-                    } else {
-                        (lhs as u64).rotate_right(rhs) as i64
-                    }
-                    .into(),
-                )
+                self.push(if direction {
+                    lhs.rotate_left(rhs)
+                } else {
+                    (lhs as u64).rotate_right(rhs) as i64
+                })?
             }
             Instruction::Cast(ty, reg) => {
                 let value = reg!(self.reg);
                 if value.get_type() == ty {
-                    self.stack.push(true.into());
-                    return Ok(None); // early return
+                    self.push(true)?;
+                    return Ok(Flow::Continue); // early return
                 }
                 *value = match (value.clone(), ty) {
                     (Value::Boolean(value), Type::Integer) => (value as i64).into(),
@@ -283,8 +424,8 @@ impl<R: Rng> Interpreter<R> {
             Instruction::Reinterpret(ty, reg) => {
                 let value = reg!(self.reg);
                 if value.get_type() == ty {
-                    self.stack.push(true.into());
-                    return Ok(None); // early return
+                    self.push(true)?;
+                    return Ok(Flow::Continue); // early return
                 }
                 *value = match (value.clone(), ty) {
                     (Value::Boolean(value), Type::Integer) => (value as i64).into(),
@@ -296,66 +437,35 @@ impl<R: Rng> Interpreter<R> {
                 }
             }
             Instruction::Input(ty, reg) => {
-                let mut buffered = BufReader::new(input);
-                let mut parsed = None;
-                let mut string = String::new();
-                while parsed.is_none() {
-                    buffered.read_line(&mut string)?;
-                    let trimmed = string.trim();
-                    // Mapping with Value::from works, .map can take more than closures
-                    parsed = match ty {
-                        Type::Integer => i64::from_str(trimmed).ok().map(Value::from),
-                        Type::Float => f64::from_str(trimmed).ok().map(Value::from),
-                        Type::Boolean => bool::from_str(trimmed).ok().map(Value::from),
-                        // Grab the first byte of the string and treat that as the character
-                        Type::Character => trimmed.bytes().next().map(Value::from),
-                    }
-                }
-                *self.register(reg) = Some(parsed.unwrap())
+                *self.register(reg) = Some(self.input_value(ty)?)
             }
             Instruction::Read(ty, reg) => {
-                let mut parsed = None;
-                while parsed.is_none() {
-                    // Mapping with Value::from works, .map can take more than closures
-                    parsed = match ty {
-                        Type::Integer => {
-                            let mut buf = [0; 8];
-                            input.read_exact(&mut buf)?;
-                            Some(i64::from_be_bytes(buf).into())
-                        }
-                        Type::Float => {
-                            let mut buf = [0; 8];
-                            input.read_exact(&mut buf)?;
-                            Some(f64::from_be_bytes(buf).into())
-                        }
-                        Type::Boolean => {
-                            let mut buf = [0];
-                            input.read_exact(&mut buf)?;
-                            Some((buf[0] != 0).into())
-                        }
-                        Type::Character => {
-                            let mut buf = [0];
-                            input.read_exact(&mut buf)?;
-                            Some(buf[0].into())
-                        }
-                    }
-                }
-                *self.register(reg) = parsed;
+                *self.register(reg) = Some(self.read_value(ty)?)
+            }
+            Instruction::TryInput(ty, reg) => {
+                let value = self.input_value(ty);
+                self.try_store(reg, value)?
+            }
+            Instruction::TryRead(ty, reg) => {
+                let value = self.read_value(ty);
+                self.try_store(reg, value)?
             }
             Instruction::Output(reg) => {
                 let value = take!(self.reg);
-                write!(output, "{value}").map_err(|_| Error::WriteFailed)?;
+                self.io
+                    .write(value.to_string().as_bytes())
+                    .map_err(|err| Error::WriteFailed(err.kind()))?;
             }
             Instruction::Write(reg) => {
                 let value = take!(self.reg);
                 // These all have the same type so we can map err outside
                 match value {
-                    Value::Integer(i) => output.write_all(&i.to_be_bytes()),
-                    Value::Float(f) => output.write_all(&f.to_be_bytes()),
-                    Value::Boolean(b) => output.write_all(&[b as u8]),
-                    Value::Character(c) => output.write_all(&[c]),
+                    Value::Integer(i) => self.io.write(&i.to_be_bytes()),
+                    Value::Float(f) => self.io.write(&f.to_be_bytes()),
+                    Value::Boolean(b) => self.io.write(&[b as u8]),
+                    Value::Character(c) => self.io.write(&[c]),
                 }
-                .map_err(|_| Error::WriteFailed)?;
+                .map_err(|err| Error::WriteFailed(err.kind()))?;
             }
             Instruction::Random(ty, reg) => {
                 *self.register(reg) = Some(match ty {
@@ -365,7 +475,7 @@ impl<R: Rng> Interpreter<R> {
                     Type::Character => self.rng.gen::<u8>().into(),
                 })
             }
-            Instruction::Break => return Ok(Some(usize::MAX)),
+            Instruction::Break => return Ok(Flow::Break),
             Instruction::Drop(reg) => *self.register(reg) = None,
             Instruction::Debug => {
                 eprint!("Debugging");
@@ -380,6 +490,31 @@ impl<R: Rng> Interpreter<R> {
                 )
             }
         }
-        Ok(None)
+        Ok(Flow::Continue)
+    }
+
+    /// Run a program in this interpreter until it stops,
+    /// flushing the output after every instruction.
+    pub fn run(&mut self, program: &Program) -> Outcome {
+        self.run_observed(program, ())
+    }
+
+    /// Run a program like [`Interpreter::run`],
+    /// calling a hook after every instruction that was executed, including ones that failed.
+    pub fn run_traced(&mut self, program: &Program, hook: impl FnMut(&Trace)) -> Outcome {
+        self.run_observed(program, TraceHook(hook))
+    }
+
+    /// Run a program like [`Interpreter::run`], letting an observer watch every step.
+    pub fn run_observed(&mut self, program: &Program, mut observer: impl Observer) -> Outcome {
+        let mut machine = Machine::new(self, program);
+        loop {
+            let result = machine.step_with(&mut observer);
+            let _ = machine.interpreter.io.flush();
+            if result.is_stopped() {
+                // Stopped machines always have an outcome
+                return machine.outcome().unwrap().clone();
+            }
+        }
     }
 }
@@ -0,0 +1,482 @@
+use crate::json::{read_message, write_message, Json};
+use crate::parser::Words;
+use crate::structures::*;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+/// The error code for requests that aren't supported.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Instructions that take a label.
+const LABEL_INSTRUCTIONS: [&str; 3] = ["jump", "branch", "call"];
+const TYPES: [&str; 4] = ["integer", "float", "boolean", "character"];
+const REGISTERS: [&str; 2] = ["X", "Y"];
+
+/// The documentation of an instruction, from the README.
+struct Doc {
+    /// An example of the instruction, like `push integer 100`.
+    example: &'static str,
+    description: String,
+}
+
+/// Read the documentation of every instruction from the README.
+fn docs() -> Vec<Doc> {
+    let readme = include_str!("../README.txt");
+    let mut docs = Vec::new();
+    // Instructions listed together share the description after them
+    let mut undescribed = 0;
+    for line in readme.lines().skip_while(|line| *line != "Instructions") {
+        if let Some(example) = line.strip_prefix("- ") {
+            if docs
+                .last()
+                .is_some_and(|doc: &Doc| !doc.description.is_empty())
+            {
+                undescribed = 0;
+            }
+            docs.push(Doc {
+                example,
+                description: String::new(),
+            });
+            undescribed += 1;
+        } else if line.starts_with(char::is_whitespace) || line.is_empty() {
+            let start = docs.len().saturating_sub(undescribed);
+            for doc in &mut docs[start..] {
+                doc.description.push_str(line.trim());
+                doc.description.push('\n');
+            }
+        }
+    }
+    for doc in &mut docs {
+        doc.description = doc.description.trim().to_string();
+    }
+    docs
+}
+
+/// A label's name, either where it's defined or where an instruction uses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol<'a> {
+    name: &'a str,
+    line: usize,
+    /// The byte columns of the name.
+    columns: Range<usize>,
+    definition: bool,
+}
+
+/// Find every label definition and use in some source code.
+fn symbols(text: &str) -> Vec<Symbol<'_>> {
+    let mut symbols = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let mut words = Words::new(content);
+        let Some(first) = words.next() else {
+            continue;
+        };
+        if !content.starts_with(|c: char| c.is_ascii_whitespace()) {
+            if !first.text.starts_with('*') {
+                symbols.push(Symbol {
+                    name: first.text,
+                    line,
+                    columns: first.column..first.column + first.text.len(),
+                    definition: true,
+                });
+            }
+        } else if LABEL_INSTRUCTIONS.contains(&first.text) {
+            if let Some(label) = words.next() {
+                symbols.push(Symbol {
+                    name: label.text,
+                    line,
+                    columns: label.column..label.column + label.text.len(),
+                    definition: false,
+                });
+            }
+        }
+    }
+    symbols
+}
+
+/// Convert a byte column within a line into a UTF-16 one, which is what positions use.
+fn utf16_column(line: &str, column: usize) -> usize {
+    line.get(..column).unwrap_or(line).encode_utf16().count()
+}
+
+/// Convert a UTF-16 column within a line into a byte one.
+fn byte_column(line: &str, column: usize) -> usize {
+    let mut units = 0;
+    for (byte, c) in line.char_indices() {
+        if units >= column {
+            return byte;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Get a line of some text, including the empty line after a final newline,
+/// which `str::lines` leaves out.
+fn nth_line(text: &str, line: usize) -> Option<&str> {
+    let content = text.split('\n').nth(line)?;
+    Some(content.strip_suffix('\r').unwrap_or(content))
+}
+
+/// Create a range within a line of some text, from byte columns.
+fn range(text: &str, line: usize, columns: &Range<usize>) -> Json {
+    let content = nth_line(text, line).unwrap_or_default();
+    let position = |column| {
+        Json::object([
+            ("line", line.into()),
+            ("character", utf16_column(content, column).into()),
+        ])
+    };
+    Json::object([
+        ("start", position(columns.start)),
+        ("end", position(columns.end)),
+    ])
+}
+
+/// A server for the Language Server Protocol,
+/// giving editors diagnostics, navigation, hovers, completion and symbols for programs.
+pub struct LspServer<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    /// The text of every open document, by URI.
+    documents: HashMap<String, String>,
+    docs: Vec<Doc>,
+}
+
+impl<R: BufRead, W: Write> LspServer<R, W> {
+    /// Create a server reading messages from an input, and writing to an output.
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            documents: HashMap::new(),
+            docs: docs(),
+        }
+    }
+
+    /// Send a message, marking it as JSON-RPC.
+    fn send(&mut self, mut entries: Vec<(&str, Json)>) -> io::Result<()> {
+        entries.insert(0, ("jsonrpc", "2.0".into()));
+        write_message(&mut self.output, &Json::object(entries))
+    }
+
+    /// Serve messages until the client asks to exit, or the input ends.
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(message) = read_message(&mut self.input)? {
+            let method = message
+                .get("method")
+                .and_then(Json::as_str)
+                .unwrap_or_default();
+            let params = message.get("params").unwrap_or(&Json::Null);
+            if method == "exit" {
+                return Ok(());
+            }
+            let result = match method {
+                "initialize" => Some(capabilities()),
+                "shutdown" => Some(Json::Null),
+                "textDocument/didOpen" => {
+                    let document = params.get("textDocument").unwrap_or(&Json::Null);
+                    let text = document.get("text").and_then(Json::as_str);
+                    self.update(uri(params), text.unwrap_or_default().to_string())?;
+                    None
+                }
+                "textDocument/didChange" => {
+                    // Only full changes are asked for, so the last one has the whole text
+                    let changes = params.get("contentChanges").and_then(Json::as_array);
+                    let text = changes
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change.get("text"))
+                        .and_then(Json::as_str);
+                    if let Some(text) = text {
+                        self.update(uri(params), text.to_string())?;
+                    }
+                    None
+                }
+                "textDocument/didClose" => {
+                    let uri = uri(params);
+                    self.documents.remove(&uri);
+                    self.publish(&uri, Vec::new())?;
+                    None
+                }
+                "textDocument/hover" => Some(self.hover(params)),
+                "textDocument/definition" => Some(self.definition(params)),
+                "textDocument/references" => Some(self.references(params)),
+                "textDocument/completion" => Some(self.completion(params)),
+                "textDocument/documentSymbol" => Some(self.document_symbols(params)),
+                _ => None,
+            };
+            // Notifications have no ID, and are never answered
+            let Some(id) = message.get("id").cloned() else {
+                continue;
+            };
+            match result {
+                Some(result) => self.send(vec![("id", id), ("result", result)])?,
+                None => {
+                    let error = Json::object([
+                        ("code", METHOD_NOT_FOUND.into()),
+                        ("message", format!("Unsupported method `{method}`.").into()),
+                    ]);
+                    self.send(vec![("id", id), ("error", error)])?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the new text of a document, and publish its parsing errors.
+    fn update(&mut self, uri: String, text: String) -> io::Result<()> {
+        let diagnostics = match Program::parse_all(&text) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors
+                .iter()
+                .map(|err| {
+                    Json::object([
+                        ("range", range(&text, err.line, &err.columns)),
+                        ("severity", 1i64.into()),
+                        ("source", "pancake".into()),
+                        ("message", err.to_string().into()),
+                    ])
+                })
+                .collect(),
+        };
+        self.publish(&uri, diagnostics)?;
+        self.documents.insert(uri, text);
+        Ok(())
+    }
+
+    /// Replace the diagnostics of a document.
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        let params = Json::object([
+            ("uri", uri.into()),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]);
+        self.send(vec![
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", params),
+        ])
+    }
+
+    /// Get the text of the document a request is about, and the position it's at,
+    /// as a line and a byte column.
+    fn position<'a>(&'a self, params: &Json) -> Option<(&'a str, usize, usize)> {
+        let text = self.documents.get(&uri(params))?;
+        let position = params.get("position")?;
+        let line = usize::try_from(position.get("line")?.as_i64()?).ok()?;
+        let character = usize::try_from(position.get("character")?.as_i64()?).ok()?;
+        let column = byte_column(nth_line(text, line)?, character);
+        Some((text, line, column))
+    }
+
+    /// Get the name of the label at the position a request is about.
+    fn label_at<'a>(&'a self, params: &Json) -> Option<(&'a str, &'a str)> {
+        let (text, line, column) = self.position(params)?;
+        let symbol = symbols(text).into_iter().find(|symbol| {
+            // The end is included, so the cursor can be right after the name
+            symbol.line == line && (symbol.columns.start..=symbol.columns.end).contains(&column)
+        })?;
+        Some((text, symbol.name))
+    }
+
+    /// Create a location in the document a request is about.
+    fn location(&self, params: &Json, text: &str, symbol: &Symbol) -> Json {
+        Json::object([
+            ("uri", uri(params).into()),
+            ("range", range(text, symbol.line, &symbol.columns)),
+        ])
+    }
+
+    /// Describe the label or the instruction being hovered over.
+    fn hover(&self, params: &Json) -> Json {
+        if let Some((text, name)) = self.label_at(params) {
+            let definition = symbols(text)
+                .into_iter()
+                .find(|symbol| symbol.definition && symbol.name == name);
+            let contents = match definition {
+                Some(symbol) => format!("Label `{name}`, defined at line {}.", symbol.line + 1),
+                None => format!("Label `{name}`, which isn't defined."),
+            };
+            return markdown(contents);
+        }
+        let Some((text, line, _)) = self.position(params) else {
+            return Json::Null;
+        };
+        let content = nth_line(text, line).unwrap_or_default();
+        if !content.starts_with(|c: char| c.is_ascii_whitespace()) {
+            return Json::Null;
+        }
+        let mut words = Words::new(content);
+        let Some(name) = words.next() else {
+            return Json::Null;
+        };
+        let argument = words.next().map(|word| word.text);
+        let first_word = |doc: &&Doc| doc.example.split(' ').next();
+        let matching: Vec<&Doc> = self
+            .docs
+            .iter()
+            .filter(|doc| match name.text {
+                comment if comment.starts_with('*') => doc.example == "*...",
+                name => first_word(doc) == Some(name),
+            })
+            .collect();
+        // Push is documented once per type, so only show the one being used
+        let specific: Vec<&Doc> = matching
+            .iter()
+            .copied()
+            .filter(|doc| doc.example.split(' ').nth(1) == argument)
+            .collect();
+        let shown = if specific.is_empty() {
+            matching
+        } else {
+            specific
+        };
+        if shown.is_empty() {
+            return Json::Null;
+        }
+        let contents: Vec<String> = shown
+            .iter()
+            .map(|doc| format!("```\n{}\n```\n{}", doc.example, doc.description))
+            .collect();
+        markdown(contents.join("\n\n"))
+    }
+
+    /// Find where the label at a position is defined.
+    fn definition(&self, params: &Json) -> Json {
+        let Some((text, name)) = self.label_at(params) else {
+            return Json::Null;
+        };
+        symbols(text)
+            .iter()
+            .find(|symbol| symbol.definition && symbol.name == name)
+            .map_or(Json::Null, |symbol| self.location(params, text, symbol))
+    }
+
+    /// Find everywhere the label at a position is used,
+    /// including where it's defined if asked for.
+    fn references(&self, params: &Json) -> Json {
+        let Some((text, name)) = self.label_at(params) else {
+            return Json::Null;
+        };
+        let declaration = params
+            .get("context")
+            .and_then(|context| context.get("includeDeclaration"))
+            .and_then(Json::as_bool)
+            .unwrap_or(true);
+        let locations = symbols(text)
+            .iter()
+            .filter(|symbol| symbol.name == name && (declaration || !symbol.definition))
+            .map(|symbol| self.location(params, text, symbol))
+            .collect();
+        Json::Array(locations)
+    }
+
+    /// Suggest instructions at the start of a line, labels after instructions that take them,
+    /// and types and registers anywhere else.
+    fn completion(&self, params: &Json) -> Json {
+        let Some((text, line, column)) = self.position(params) else {
+            return Json::Array(Vec::new());
+        };
+        let content = nth_line(text, line).unwrap_or_default();
+        let before: Vec<&str> = content[..column].split_ascii_whitespace().collect();
+        // A word that's still being typed doesn't count
+        let finished = match content[..column].ends_with(|c: char| c.is_ascii_whitespace()) {
+            true => before.len(),
+            false => before.len().saturating_sub(1),
+        };
+        let item = |label: &str, kind: i64, detail: &str| {
+            Json::object([
+                ("label", label.into()),
+                ("kind", kind.into()),
+                ("detail", detail.into()),
+            ])
+        };
+        let mut items = Vec::new();
+        match before.first() {
+            _ if finished == 0 => {
+                let mut names: Vec<&str> = self
+                    .docs
+                    .iter()
+                    .filter_map(|doc| doc.example.split(' ').next())
+                    .filter(|name| !name.starts_with('*'))
+                    .collect();
+                names.dedup();
+                for name in names {
+                    items.push(item(name, 14, "instruction"));
+                }
+            }
+            Some(instruction) if LABEL_INSTRUCTIONS.contains(instruction) => {
+                for symbol in symbols(text).iter().filter(|symbol| symbol.definition) {
+                    items.push(item(symbol.name, 18, "label"));
+                }
+            }
+            _ => {
+                for ty in TYPES {
+                    items.push(item(ty, 25, "type"));
+                }
+                for register in REGISTERS {
+                    items.push(item(register, 6, "register"));
+                }
+            }
+        }
+        Json::Array(items)
+    }
+
+    /// List every label defined in a document.
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = uri(params);
+        let Some(text) = self.documents.get(&uri) else {
+            return Json::Array(Vec::new());
+        };
+        let symbols = symbols(text)
+            .iter()
+            .filter(|symbol| symbol.definition)
+            .map(|symbol| {
+                let range = range(text, symbol.line, &symbol.columns);
+                Json::object([
+                    ("name", symbol.name.into()),
+                    // Labels are shown as functions, as they're usually called like them
+                    ("kind", 12i64.into()),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ])
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+}
+
+/// Get the URI of the document that a request or notification is about.
+fn uri(params: &Json) -> String {
+    params
+        .get("textDocument")
+        .and_then(|document| document.get("uri"))
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The result of an initialize request, saying what the server can do.
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Documents are always sent in full
+                ("textDocumentSync", 1i64.into()),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("referencesProvider", true.into()),
+                ("completionProvider", Json::object::<&str>([])),
+                ("documentSymbolProvider", true.into()),
+            ]),
+        ),
+        ("serverInfo", Json::object([("name", "pancake".into())])),
+    ])
+}
+
+/// Create a hover result holding some markdown.
+fn markdown(contents: String) -> Json {
+    Json::object([(
+        "contents",
+        Json::object([("kind", "markdown".into()), ("value", contents.into())]),
+    )])
+}
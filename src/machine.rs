@@ -0,0 +1,290 @@
+use crate::io::Io;
+use crate::observer::{JumpKind, Observer, State};
+use crate::structures::*;
+use crate::trace::Trace;
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of executing a single instruction.
+pub enum StepResult {
+    /// Execution moved on to the next instruction.
+    Continue,
+    /// Execution jumped to an instruction index.
+    Jumped(usize),
+    /// Execution went past the end of the program, or jumped outside of it.
+    Halted,
+    /// A break instruction was executed.
+    Break,
+    /// The instruction failed to execute.
+    Errored(Error),
+}
+
+impl StepResult {
+    /// Check whether the machine stopped after this step.
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, Self::Halted | Self::Break | Self::Errored(_))
+    }
+}
+
+#[derive(Debug)]
+/// An interpreter running a program, keeping track of where it is.
+///
+/// This owns the instruction pointer, so it can be stepped through
+/// one instruction at a time.
+pub struct Machine<'a, R: Rng, I: Io> {
+    /// The interpreter executing the program.
+    pub interpreter: &'a mut Interpreter<R, I>,
+    program: &'a Program,
+    pub(crate) pc: usize,
+    pub(crate) steps: u64,
+    pub(crate) outcome: Option<Outcome>,
+    given: Given,
+}
+
+#[derive(Debug, Default)]
+/// The registers and the top of the stack that an instruction was given,
+/// kept so that failures can show them, as failing may have taken them already.
+struct Given {
+    x: Option<Value>,
+    y: Option<Value>,
+    stack_top: Vec<Value>,
+    stack_length: usize,
+}
+
+impl Given {
+    fn keep<R: Rng, I: Io>(&mut self, interpreter: &Interpreter<R, I>) {
+        let stack = &interpreter.stack;
+        self.x.clone_from(&interpreter.x);
+        self.y.clone_from(&interpreter.y);
+        self.stack_top.clear();
+        let start = stack.len().saturating_sub(RuntimeError::STACK_SNAPSHOT);
+        self.stack_top.extend_from_slice(&stack[start..]);
+        self.stack_length = stack.len();
+    }
+}
+
+impl<'a, R: Rng, I: Io> Machine<'a, R, I> {
+    /// Create a machine at the start of a program.
+    pub fn new(interpreter: &'a mut Interpreter<R, I>, program: &'a Program) -> Self {
+        Self {
+            interpreter,
+            program,
+            pc: 0,
+            steps: 0,
+            outcome: None,
+            given: Given::default(),
+        }
+    }
+
+    /// Get the program this machine is running.
+    pub fn program(&self) -> &'a Program {
+        self.program
+    }
+
+    /// Get the index of the next instruction to execute.
+    /// If the machine errored, this is the index of the instruction that failed.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Move the instruction pointer to an index, resuming the machine if it was stopped.
+    pub fn set_pc(&mut self, index: usize) {
+        self.pc = index;
+        self.outcome = None;
+    }
+
+    /// Get the amount of instructions this machine has executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Get the instruction at the instruction pointer, alongside the line it's on.
+    pub fn current(&self) -> Option<(usize, Instruction)> {
+        self.program.get(self.pc)
+    }
+
+    /// Get how the machine stopped, if it did.
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.outcome.as_ref()
+    }
+
+    /// Check whether the machine has stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// Execute the instruction at the instruction pointer.
+    /// Stepping a stopped machine does nothing, and returns why it stopped.
+    pub fn step(&mut self) -> StepResult {
+        self.step_with(&mut ())
+    }
+
+    /// Execute the instruction at the instruction pointer, letting an observer watch.
+    /// Stepping a stopped machine does nothing, and returns why it stopped.
+    pub fn step_with(&mut self, observer: &mut dyn Observer) -> StepResult {
+        if let Some(outcome) = &self.outcome {
+            return match outcome {
+                Outcome::Halted => StepResult::Halted,
+                Outcome::Break => StepResult::Break,
+                Outcome::Errored(err) => StepResult::Errored(err.error.clone()),
+            };
+        }
+        let Some((line, instr)) = self.current() else {
+            self.outcome = Some(Outcome::Halted);
+            return StepResult::Halted;
+        };
+        if self
+            .interpreter
+            .max_steps
+            .is_some_and(|max| self.steps >= max)
+        {
+            let result = self.fail(Error::FuelExhausted(self.steps), line, instr);
+            if let Some(Outcome::Errored(err)) = &self.outcome {
+                observer.errored(err);
+            }
+            return result;
+        }
+        let index = self.pc;
+        let interpreter = &*self.interpreter;
+        observer.before(&State {
+            program: self.program,
+            steps: self.steps,
+            index,
+            line,
+            instruction: instr,
+            x: &interpreter.x,
+            y: &interpreter.y,
+            stack: &interpreter.stack,
+        });
+        // Output takes the value out of its register, so it has to be kept around
+        let written = match instr {
+            Instruction::Output(reg) | Instruction::Write(reg) => self.register(reg).clone(),
+            _ => None,
+        };
+        let (result, jumped) = self.execute(line, instr);
+        if !matches!(result, StepResult::Errored(_)) {
+            self.notify(observer, index, instr, jumped, written);
+        }
+        let interpreter = &*self.interpreter;
+        observer.after(&Trace {
+            step: self.steps - 1,
+            index,
+            line,
+            instruction: instr,
+            x: &interpreter.x,
+            y: &interpreter.y,
+            stack: &interpreter.stack,
+            error: match &result {
+                StepResult::Errored(err) => Some(err),
+                _ => None,
+            },
+        });
+        if let Some(Outcome::Errored(err)) = &self.outcome {
+            observer.errored(err);
+        }
+        result
+    }
+
+    /// Execute an instruction that's known to exist, without checking for fuel.
+    /// Also returns whether the instruction jumped, even if that halted the machine.
+    fn execute(&mut self, line: usize, instr: Instruction) -> (StepResult, bool) {
+        self.steps += 1;
+        self.given.keep(self.interpreter);
+        let flow = self.interpreter.execute(self.pc, instr, Some(line));
+        let jumped = matches!(flow, Ok(Flow::Jump(_)));
+        let result = match flow {
+            Ok(Flow::Continue) => {
+                self.pc += 1;
+                StepResult::Continue
+            }
+            Ok(Flow::Jump(to)) => {
+                self.pc = to;
+                StepResult::Jumped(to)
+            }
+            Ok(Flow::Halt) => StepResult::Halted,
+            Ok(Flow::Break) => StepResult::Break,
+            Err(error) => return (self.fail(error, line, instr), false),
+        };
+        // Leaving the program halts it right away, instead of on the next step
+        let result = match result {
+            StepResult::Continue | StepResult::Jumped(_) if self.pc >= self.program.len() => {
+                StepResult::Halted
+            }
+            result => result,
+        };
+        self.outcome = match result {
+            StepResult::Halted => Some(Outcome::Halted),
+            StepResult::Break => Some(Outcome::Break),
+            _ => None,
+        };
+        (result, jumped)
+    }
+
+    /// Tell an observer about any jumps or I/O done by an instruction that succeeded.
+    fn notify(
+        &self,
+        observer: &mut dyn Observer,
+        index: usize,
+        instr: Instruction,
+        jumped: bool,
+        written: Option<Value>,
+    ) {
+        let kind = match instr {
+            Instruction::Jump(_) => Some(JumpKind::Jump),
+            Instruction::Branch(_) => Some(JumpKind::Branch),
+            Instruction::Goto(_) => Some(JumpKind::Goto),
+            Instruction::Call(_) => Some(JumpKind::Call),
+            Instruction::Return => Some(JumpKind::Return),
+            _ => None,
+        };
+        // Jumping out of the program halts it, but the target is still kept
+        if let Some(kind) = kind.filter(|_| jumped) {
+            observer.jumped(kind, index, self.pc);
+        }
+        match instr {
+            Instruction::Input(_, reg) | Instruction::Read(_, reg) => {
+                if let Some(value) = self.register(reg) {
+                    observer.input(index, value);
+                }
+            }
+            Instruction::TryInput(_, reg) | Instruction::TryRead(_, reg) => {
+                if let (Some(Value::Boolean(true)), Some(value)) =
+                    (self.interpreter.stack.last(), self.register(reg))
+                {
+                    observer.input(index, value);
+                }
+            }
+            Instruction::Output(_) | Instruction::Write(_) => {
+                if let Some(value) = &written {
+                    observer.output(index, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Get the value in a register.
+    fn register(&self, register: Register) -> &Option<Value> {
+        match register {
+            Register::X => &self.interpreter.x,
+            Register::Y => &self.interpreter.y,
+        }
+    }
+
+    /// Stop the machine, as the instruction at the instruction pointer failed.
+    fn fail(&mut self, error: Error, line: usize, instr: Instruction) -> StepResult {
+        let given = std::mem::take(&mut self.given);
+        self.outcome = Some(Outcome::Errored(RuntimeError {
+            error: error.clone(),
+            index: self.pc,
+            line,
+            instruction: instr,
+            text: self.program.source_text(instr),
+            x: given.x,
+            y: given.y,
+            stack_top: given.stack_top,
+            stack_length: given.stack_length,
+        }));
+        StepResult::Errored(error)
+    }
+}
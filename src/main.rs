@@ -1,66 +1,443 @@
 use std::{
     borrow::Borrow,
     env::args_os,
-    io::{stdin, stdout},
+    ffi::OsString,
+    fs::File,
+    io::{self, BufWriter, Write},
     process::ExitCode,
+    str::FromStr,
 };
-use std::io::Write;
 
-use pancake::Interpreter;
+use pancake::{
+    check, Cfg, Coverage, DapServer, Debugger, InputRetry, Interpreter, Io, LspServer, Machine,
+    Observer, Outcome, Profiler, Program, Stdio, TraceHook, Type,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Stdin and stdout, optionally prompting on stderr when input is asked for.
+struct Console {
+    prompt: bool,
+    /// Input read from a file instead of stdin.
+    input: Option<io::Cursor<Vec<u8>>>,
+}
+
+impl Io for Console {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => io::Read::read(input, buf),
+            None => Stdio.read(buf),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        Stdio.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Stdio.flush()
+    }
+
+    fn prompt(&mut self, ty: Type, rejected: usize) -> io::Result<()> {
+        if !self.prompt {
+            return Ok(());
+        }
+        // Make sure the prompt comes after any output
+        Stdio.flush()?;
+        let mut stderr = io::stderr();
+        if rejected > 0 {
+            writeln!(stderr, "That's not a valid {ty}, try again.")?;
+        }
+        write!(stderr, "{ty}> ")?;
+        stderr.flush()
+    }
+}
+
+/// Parse the value given to a flag, printing an error if it's invalid.
+fn flag_value<T: FromStr>(flag: &str, value: Option<OsString>) -> Result<T, ExitCode> {
+    match value.as_ref().and_then(|v| v.to_str()).map(T::from_str) {
+        Some(Ok(value)) => Ok(value),
+        _ => {
+            eprintln!("Invalid or missing value for {flag}");
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// What the CLI was asked to do with a program.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Run,
+    Debug,
+    Profile,
+    Cfg,
+    Check,
+    Dap,
+    Lsp,
+}
+
+impl Command {
+    /// Get the flags that change what this command does.
+    fn flags(self) -> &'static [&'static str] {
+        match self {
+            Command::Run => &[
+                "--max-steps",
+                "--max-stack",
+                "--seed",
+                "--input-retries",
+                "--prompt",
+                "--input",
+                "--trace",
+                "--trace-stack",
+                "--coverage",
+            ],
+            Command::Debug => &[
+                "--max-steps",
+                "--max-stack",
+                "--seed",
+                "--input-retries",
+                "--prompt",
+                "--input",
+            ],
+            Command::Profile => &[
+                "--max-steps",
+                "--max-stack",
+                "--seed",
+                "--input-retries",
+                "--prompt",
+                "--input",
+                "--top",
+                "--folded",
+            ],
+            Command::Cfg => &["--dot"],
+            Command::Check | Command::Dap | Command::Lsp => &[],
+        }
+    }
+}
+
+/// Read and parse a program, printing any errors.
+fn load(filepath: OsString) -> Result<(String, Program), ExitCode> {
+    // Read the file
+    // This could be read line by line, but it would require a complex
+    // system of keeping track of which instructions need labels,
+    // and that seems more complicated than I care to do for a simple project like this.
+    let source = match std::fs::read_to_string(filepath) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to read file: {err}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    match Program::parse_all(&source) {
+        Ok(program) => Ok((source, program)),
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("Parsing error: {}\n", err.render(&source));
+            }
+            eprintln!("Failed to parse program due to {} error(s)", errors.len());
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Debug a program interactively, reading commands from stdin.
+/// Programs that read input should be given it with `--input`, as they'd take the commands otherwise.
+fn debug<R: Rng, I: Io>(
+    interpreter: &mut Interpreter<R, I>,
+    program: &Program,
+    source: &str,
+) -> ExitCode {
+    let mut debugger = Debugger::new(Machine::new(interpreter, program), source);
+    let mut out = io::stdout();
+    let _ = writeln!(
+        out,
+        "Debugging {} instructions. Type `help` for a list of commands.",
+        program.len()
+    );
+    let _ = debugger.print_location(&mut out);
+    loop {
+        let _ = debugger.machine.interpreter.io.flush();
+        let _ = write!(out, "(pancake) ");
+        let _ = out.flush();
+        // Stdin can't be locked here, as the program may read from it too
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Failed to read command: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+        match debugger.command(&line, &mut out) {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("Failed to write to stdout: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+}
+
+/// Run a program with an observer, writing a trace of every executed instruction as JSON Lines.
+fn trace_run<R: Rng, I: Io>(
+    interpreter: &mut Interpreter<R, I>,
+    program: &Program,
+    path: OsString,
+    full_stack: bool,
+    observer: impl Observer,
+) -> Result<Outcome, ExitCode> {
+    let mut file = match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            eprintln!("Failed to create trace file: {err}");
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    // Keep running even if the trace can't be written, but report it afterwards
+    let mut result = Ok(());
+    let tracer = TraceHook(|trace: &_| {
+        if result.is_ok() {
+            result = writeln!(file, "{}", trace.to_json(program, full_stack));
+        }
+    });
+    let outcome = interpreter.run_observed(program, (tracer, observer));
+    if let Err(err) = result.and_then(|_| file.flush()) {
+        eprintln!("Failed to write trace: {err}");
+        return Err(ExitCode::FAILURE);
+    }
+    Ok(outcome)
+}
+
+/// Run a program while profiling it, then print a report on stderr.
+fn profile<R: Rng, I: Io>(
+    interpreter: &mut Interpreter<R, I>,
+    program: &Program,
+    source: &str,
+    folded: Option<OsString>,
+    top: usize,
+) -> ExitCode {
+    let mut profiler = Profiler::new(program);
+    let outcome = interpreter.run_observed(program, &mut profiler);
+    let _ = interpreter.io.flush();
+    eprint!("\n{}", profiler.report(program, source, top));
+    if let Some(path) = folded {
+        if let Err(err) = std::fs::write(path, profiler.folded()) {
+            eprintln!("Failed to write folded stacks: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Outcome::Errored(err) = outcome {
+        eprintln!("Runtime error: {}", err.render(source));
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Check a program without running it, printing a warning for every instruction that may fail.
+/// Fails if any instruction always does.
+fn check_program(program: &Program, source: &str) -> ExitCode {
+    let warnings = check(program);
+    for warning in &warnings {
+        let text = source.lines().nth(warning.line).unwrap_or_default().trim();
+        println!("Warning on {warning}\n    {text}");
+    }
+    println!("Found {} warning(s)", warnings.len());
+    match warnings.iter().any(|warning| warning.always) {
+        true => ExitCode::FAILURE,
+        false => ExitCode::SUCCESS,
+    }
+}
 
 // We use ExitCode to prevent the implicit Error: printout when using a Result<T, E>
 fn main() -> ExitCode {
     // Read the CLI arguments
-    let Some(filepath) = args_os().nth(1) else {
-        println!(include_str!("help.txt"));
-        return ExitCode::SUCCESS;
-    };
-    match filepath.to_string_lossy().borrow() {
-        "--docs" => println!(include_str!("../README.txt")),
-        "--license" => println!(include_str!("../LICENSE.txt")),
-        _ => {
-            // Grab the input and output
-            let mut input = stdin().lock();
-            let mut output = stdout().lock();
-            // Read the file
-            // This could be read line by line, but it would require a complex
-            // system of keeping track of which instructions need labels,
-            // and that seems more complicated than I care to do for a simple project like this.
-            let program = match std::fs::read_to_string(filepath) {
-                Ok(file) => file,
-                Err(err) => {
-                    eprintln!("Failed to read file: {err}");
+    let mut args = args_os().skip(1);
+    let mut command = None;
+    let mut filepath = None;
+    let mut max_steps = None;
+    let mut max_stack = None;
+    let mut seed = None;
+    let mut input_retry = InputRetry::Forever;
+    let mut prompt = false;
+    let mut input = None;
+    let mut trace = None;
+    let mut trace_stack = false;
+    let mut folded = None;
+    let mut coverage_path = None;
+    let mut top = 20;
+    let mut dot = false;
+    let mut flags = Vec::new();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if text.starts_with("--") {
+            flags.push(text.clone().into_owned());
+        }
+        match text.borrow() {
+            "--docs" => {
+                println!(include_str!("../README.txt"));
+                return ExitCode::SUCCESS;
+            }
+            "--license" => {
+                println!(include_str!("../LICENSE.txt"));
+                return ExitCode::SUCCESS;
+            }
+            "--max-steps" => match flag_value("--max-steps", args.next()) {
+                Ok(steps) => max_steps = Some(steps),
+                Err(code) => return code,
+            },
+            "--max-stack" => match flag_value("--max-stack", args.next()) {
+                Ok(size) => max_stack = Some(size),
+                Err(code) => return code,
+            },
+            "--seed" => match flag_value("--seed", args.next()) {
+                Ok(value) => seed = Some(value),
+                Err(code) => return code,
+            },
+            "--input-retries" => match flag_value("--input-retries", args.next()) {
+                Ok(0) => input_retry = InputRetry::Never,
+                Ok(times) => input_retry = InputRetry::Times(times),
+                Err(code) => return code,
+            },
+            "--prompt" => prompt = true,
+            "--input" => match args.next().map(std::fs::read) {
+                Some(Ok(contents)) => input = Some(io::Cursor::new(contents)),
+                Some(Err(err)) => {
+                    eprintln!("Failed to read input file: {err}");
+                    return ExitCode::FAILURE;
+                }
+                None => {
+                    eprintln!("Invalid or missing value for --input");
                     return ExitCode::FAILURE;
                 }
-            };
-            let program = match pancake::parse_file(program) {
-                Ok(v) => v,
-                Err((location, why)) => {
-                    // Lines are usually counted from 1
-                    eprintln!("Parsing error: {why} at line {}", location + 1);
+            },
+            "--trace" => match args.next() {
+                Some(path) => trace = Some(path),
+                None => {
+                    eprintln!("Invalid or missing value for --trace");
                     return ExitCode::FAILURE;
                 }
-            };
-            let mut interpreter = Interpreter::default();
-            // We need to jump around, so we store the index externally
-            let mut index = 0;
-            while let Some((line, instr)) = program.get(index) {
-                if let Some(new_index) =
-                    match interpreter.execute(index, *instr, &mut input, &mut output, Some(*line)) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            eprintln!("Runtime error: {err} at line #{line} ({instr:?})");
-                            return ExitCode::FAILURE;
-                        }
-                    }
-                {
-                    index = new_index;
-                } else {
-                    index += 1;
+            },
+            "--trace-stack" => trace_stack = true,
+            "--folded" => match args.next() {
+                Some(path) => folded = Some(path),
+                None => {
+                    eprintln!("Invalid or missing value for --folded");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--coverage" => match args.next() {
+                Some(path) => coverage_path = Some(path),
+                None => {
+                    eprintln!("Invalid or missing value for --coverage");
+                    return ExitCode::FAILURE;
                 }
-                let _ = output.flush();
+            },
+            "--dot" => dot = true,
+            "--top" => match flag_value("--top", args.next()) {
+                Ok(amount) => top = amount,
+                Err(code) => return code,
+            },
+            // Subcommands have to come first
+            "run" if command.is_none() && filepath.is_none() => command = Some(Command::Run),
+            "debug" if command.is_none() && filepath.is_none() => command = Some(Command::Debug),
+            "profile" if command.is_none() && filepath.is_none() => {
+                command = Some(Command::Profile)
             }
+            "check" if command.is_none() && filepath.is_none() => command = Some(Command::Check),
+            "cfg" if command.is_none() && filepath.is_none() => command = Some(Command::Cfg),
+            "dap" if command.is_none() && filepath.is_none() => command = Some(Command::Dap),
+            "lsp" if command.is_none() && filepath.is_none() => command = Some(Command::Lsp),
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown flag {flag}");
+                return ExitCode::FAILURE;
+            }
+            _ if filepath.is_some() => {
+                eprintln!("Unexpected argument {}", arg.to_string_lossy());
+                return ExitCode::FAILURE;
+            }
+            _ => filepath = Some(arg),
         }
     }
+    // Flags for other commands would silently do nothing
+    let accepted = command.unwrap_or(Command::Run).flags();
+    if let Some(flag) = flags.iter().find(|flag| !accepted.contains(&flag.as_str())) {
+        eprintln!("Unknown flag {flag}");
+        return ExitCode::FAILURE;
+    }
+    if command == Some(Command::Dap) {
+        // Programs are given by the editor instead
+        let mut server = DapServer::new(io::stdin().lock(), io::stdout().lock());
+        if let Err(err) = server.serve() {
+            eprintln!("Debug adapter failed: {err}");
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    if command == Some(Command::Lsp) {
+        // Documents are given by the editor instead
+        let mut server = LspServer::new(io::stdin().lock(), io::stdout().lock());
+        if let Err(err) = server.serve() {
+            eprintln!("Language server failed: {err}");
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    let Some(filepath) = filepath else {
+        println!(include_str!("help.txt"));
+        return ExitCode::SUCCESS;
+    };
+    let source_name = filepath.to_string_lossy().into_owned();
+    let (source, program) = match load(filepath) {
+        Ok(loaded) => loaded,
+        Err(code) => return code,
+    };
+    if command == Some(Command::Cfg) {
+        let cfg = Cfg::new(program.instructions());
+        match dot {
+            true => print!("{}", cfg.dot(&program)),
+            false => print!("{}", cfg.listing(&program)),
+        }
+        return ExitCode::SUCCESS;
+    }
+    if command == Some(Command::Check) {
+        return check_program(&program, &source);
+    }
+    let mut interpreter = match seed {
+        Some(seed) => Interpreter::seeded(seed),
+        None => Interpreter::with_rng(StdRng::from_entropy()),
+    }
+    .with_io(Console { prompt, input });
+    interpreter.max_steps = max_steps;
+    interpreter.max_stack = max_stack;
+    interpreter.input_retry = input_retry;
+    match command {
+        Some(Command::Debug) => return debug(&mut interpreter, &program, &source),
+        Some(Command::Profile) => {
+            return profile(&mut interpreter, &program, &source, folded, top);
+        }
+        _ => {}
+    }
+    let mut coverage = coverage_path.as_ref().map(|_| Coverage::new(&program));
+    let outcome = match trace {
+        Some(path) => {
+            let observer = coverage.as_mut();
+            match trace_run(&mut interpreter, &program, path, trace_stack, observer) {
+                Ok(outcome) => outcome,
+                Err(code) => return code,
+            }
+        }
+        None => interpreter.run_observed(&program, coverage.as_mut()),
+    };
+    if let (Some(path), Some(coverage)) = (coverage_path, coverage) {
+        if let Err(err) = std::fs::write(path, coverage.lcov(&program, &source_name)) {
+            eprintln!("Failed to write coverage: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Outcome::Errored(err) = outcome {
+        eprintln!("Runtime error: {}", err.render(&source));
+        return ExitCode::FAILURE;
+    }
     ExitCode::SUCCESS
 }
@@ -0,0 +1,179 @@
+use crate::structures::*;
+use crate::trace::Trace;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kind of instruction that moved execution somewhere else.
+pub enum JumpKind {
+    Jump,
+    /// A branch that was taken.
+    Branch,
+    Goto,
+    Call,
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The state of a machine right before it executes an instruction.
+pub struct State<'a> {
+    /// The program being run.
+    pub program: &'a Program,
+    /// How many instructions were executed so far.
+    pub steps: u64,
+    /// The index of the instruction about to be executed.
+    pub index: usize,
+    /// The line the instruction is on, counting from 0.
+    pub line: usize,
+    /// The instruction about to be executed.
+    pub instruction: Instruction,
+    /// The value in the X register.
+    pub x: &'a Option<Value>,
+    /// The value in the Y register.
+    pub y: &'a Option<Value>,
+    /// The whole stack, from the bottom up.
+    pub stack: &'a [Value],
+}
+
+/// Something that watches a machine run, like a tracer or a profiler.
+///
+/// For each executed instruction, [`Observer::before`] is called first,
+/// then any jump or I/O events, and then [`Observer::after`].
+/// Every method does nothing by default.
+pub trait Observer {
+    /// Called right before an instruction is executed.
+    fn before(&mut self, state: &State) {
+        let _ = state;
+    }
+    /// Called after an instruction was executed, even if it failed.
+    fn after(&mut self, trace: &Trace) {
+        let _ = trace;
+    }
+    /// Called when an instruction moved execution from one index to another.
+    /// The target may be outside of the program, which halts it.
+    fn jumped(&mut self, kind: JumpKind, from: usize, to: usize) {
+        let _ = (kind, from, to);
+    }
+    /// Called when an instruction at an index read a value from the input.
+    fn input(&mut self, index: usize, value: &Value) {
+        let _ = (index, value);
+    }
+    /// Called when an instruction at an index wrote a value to the output.
+    fn output(&mut self, index: usize, value: &Value) {
+        let _ = (index, value);
+    }
+    /// Called when the machine stops because of an error,
+    /// including running out of fuel before executing anything.
+    fn errored(&mut self, error: &RuntimeError) {
+        let _ = error;
+    }
+}
+
+/// Observes nothing.
+impl Observer for () {}
+
+/// A pair of observers can be used together, with the first one always being called first.
+/// Nest pairs to use more of them.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before(&mut self, state: &State) {
+        self.0.before(state);
+        self.1.before(state);
+    }
+
+    fn after(&mut self, trace: &Trace) {
+        self.0.after(trace);
+        self.1.after(trace);
+    }
+
+    fn jumped(&mut self, kind: JumpKind, from: usize, to: usize) {
+        self.0.jumped(kind, from, to);
+        self.1.jumped(kind, from, to);
+    }
+
+    fn input(&mut self, index: usize, value: &Value) {
+        self.0.input(index, value);
+        self.1.input(index, value);
+    }
+
+    fn output(&mut self, index: usize, value: &Value) {
+        self.0.output(index, value);
+        self.1.output(index, value);
+    }
+
+    fn errored(&mut self, error: &RuntimeError) {
+        self.0.errored(error);
+        self.1.errored(error);
+    }
+}
+
+/// An observer that may not be there only observes if it is.
+impl<O: Observer> Observer for Option<O> {
+    fn before(&mut self, state: &State) {
+        if let Some(observer) = self {
+            observer.before(state)
+        }
+    }
+
+    fn after(&mut self, trace: &Trace) {
+        if let Some(observer) = self {
+            observer.after(trace)
+        }
+    }
+
+    fn jumped(&mut self, kind: JumpKind, from: usize, to: usize) {
+        if let Some(observer) = self {
+            observer.jumped(kind, from, to)
+        }
+    }
+
+    fn input(&mut self, index: usize, value: &Value) {
+        if let Some(observer) = self {
+            observer.input(index, value)
+        }
+    }
+
+    fn output(&mut self, index: usize, value: &Value) {
+        if let Some(observer) = self {
+            observer.output(index, value)
+        }
+    }
+
+    fn errored(&mut self, error: &RuntimeError) {
+        if let Some(observer) = self {
+            observer.errored(error)
+        }
+    }
+}
+
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn before(&mut self, state: &State) {
+        (**self).before(state)
+    }
+
+    fn after(&mut self, trace: &Trace) {
+        (**self).after(trace)
+    }
+
+    fn jumped(&mut self, kind: JumpKind, from: usize, to: usize) {
+        (**self).jumped(kind, from, to)
+    }
+
+    fn input(&mut self, index: usize, value: &Value) {
+        (**self).input(index, value)
+    }
+
+    fn output(&mut self, index: usize, value: &Value) {
+        (**self).output(index, value)
+    }
+
+    fn errored(&mut self, error: &RuntimeError) {
+        (**self).errored(error)
+    }
+}
+
+/// An observer that calls a trace hook after every instruction.
+pub struct TraceHook<F: FnMut(&Trace)>(pub F);
+
+impl<F: FnMut(&Trace)> Observer for TraceHook<F> {
+    fn after(&mut self, trace: &Trace) {
+        (self.0)(trace)
+    }
+}
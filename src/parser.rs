@@ -26,22 +26,105 @@ impl FromStr for Register {
     }
 }
 
+/// A word within a line, and where it starts.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Word<'a> {
+    pub(crate) column: usize,
+    pub(crate) text: &'a str,
+}
+
+impl Word<'_> {
+    /// Create an error pointing at this word.
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            // The line is filled in by whoever knows it
+            line: 0,
+            columns: self.column..self.column + self.text.len(),
+            token: self.text.to_string(),
+            kind,
+        }
+    }
+}
+
+/// An iterator over the whitespace-separated words of a line.
+pub(crate) struct Words<'a> {
+    line: &'a str,
+    position: usize,
+}
+
+impl<'a> Words<'a> {
+    pub(crate) fn new(line: &'a str) -> Self {
+        Self { line, position: 0 }
+    }
+
+    /// Create an error pointing at the end of the line, for when a word is missing.
+    fn end_error(&self, kind: ParseErrorKind) -> ParseError {
+        let end = self.line.trim_end().len();
+        ParseError {
+            line: 0,
+            columns: end..end,
+            token: String::new(),
+            kind,
+        }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = Word<'a>;
+
+    fn next(&mut self) -> Option<Word<'a>> {
+        let rest = &self.line[self.position..];
+        let start = self.position + rest.find(|c: char| !c.is_ascii_whitespace())?;
+        let rest = &self.line[start..];
+        let length = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        self.position = start + length;
+        Some(Word {
+            column: start,
+            text: &rest[..length],
+        })
+    }
+}
+
 // Macros for ergonomics within the match statement below
 
+macro_rules! expected {
+    ($word: ident, $what: literal) => {
+        return Err($word.error(ParseErrorKind::Expected($what)))
+    };
+}
+
 macro_rules! parse_register {
     ($name: ident) => {{
-        let Ok(register) = Register::from_str($name) else {
-            return Err(Error::InvalidInstruction);
+        let Ok(register) = Register::from_str($name.text) else {
+            expected!($name, "register X or Y");
         };
         register
     }};
 }
 
+macro_rules! parse_type_name {
+    ($name: ident) => {{
+        let Ok(ty) = Type::from_str($name.text) else {
+            expected!($name, "type integer, float, boolean or character");
+        };
+        ty
+    }};
+}
+
+macro_rules! parse_label {
+    ($labels: ident, $name: ident) => {{
+        let Some(index) = $labels.get($name.text) else {
+            return Err($name.error(ParseErrorKind::MissingLabel($name.text.to_string())));
+        };
+        *index
+    }};
+}
+
 macro_rules! parse_type {
     ($value: ident as char) => {{
-        let bytes = $value.as_bytes();
+        let bytes = $value.text.as_bytes();
         if bytes.len() != 3 {
-            return Err(Error::InvalidInstruction);
+            expected!($value, "character like 'H' or #48");
         };
         if bytes[0] == bytes[2] && bytes[0] == b'\'' {
             bytes[1]
@@ -49,110 +132,110 @@ macro_rules! parse_type {
             let string = [bytes[1], bytes[2]];
             let string = String::from_utf8_lossy(&string);
             let Ok(value) = u8::from_str_radix(&string, 16) else {
-                return Err(Error::InvalidInstruction);
+                expected!($value, "hexadecimal character like #48");
             };
             value
         } else {
-            eprintln!("bad formatting");
-
-            return Err(Error::InvalidInstruction);
+            expected!($value, "character like 'H' or #48");
         }
     }};
-    ($value: ident as $ty: ty) => {{
-        let Ok(value) = <$ty>::from_str($value) else {
-            return Err(Error::InvalidInstruction);
+    ($value: ident as $ty: ty, $what: literal) => {{
+        let Ok(value) = <$ty>::from_str($value.text) else {
+            expected!($value, $what);
         };
         value
     }};
 }
 
 macro_rules! next_word {
-    ($words: ident => $target: ident) => {
+    ($words: ident => $target: ident, $what: expr) => {
         let Some($target) = $words.next() else {
-            return Err(Error::InvalidInstruction);
+            return Err($words.end_error(ParseErrorKind::Expected($what)));
         };
     };
 }
 
 impl Instruction {
     /// Parse an instruction.
+    /// Errors point at columns within the given line, but not at a line number.
     pub fn parse(
-        mut line: &str,
+        line: &str,
         labels: &HashMap<&str, usize>,
-    ) -> Result<Option<Instruction>, Error> {
+    ) -> Result<Option<Instruction>, ParseError> {
         // Split the instruction into its parts
-        line = line.trim();
-        let mut words = line.split_ascii_whitespace();
+        let mut words = Words::new(line);
         let Some(instruction_name) = words.next() else {
             // Just whitespace here, moving along
             return Ok(None);
         };
         // Giant match
-        match instruction_name {
+        match instruction_name.text {
             "push" => {
                 // Either a type or register
-                next_word!(words => kind);
-                next_word!(words => value);
-                Ok(match kind {
-                    "integer" => Some(Instruction::PushInteger(parse_type!(value as i64))),
-                    "float" => Some(Instruction::PushFloat(parse_type!(value as f64))),
-                    "boolean" => Some(Instruction::PushBoolean(parse_type!(value as bool))),
+                next_word!(words => kind, "type or register");
+                let expectation = match kind.text {
+                    "integer" => "integer",
+                    "float" => "float",
+                    "boolean" => "boolean",
+                    "character" => "character like 'H' or #48",
+                    "register" => "register X or Y",
+                    _ => expected!(kind, "type or register"),
+                };
+                next_word!(words => value, expectation);
+                Ok(match kind.text {
+                    "integer" => Some(Instruction::PushInteger(parse_type!(value as i64, "integer"))),
+                    "float" => Some(Instruction::PushFloat(parse_type!(value as f64, "float"))),
+                    "boolean" => Some(Instruction::PushBoolean(parse_type!(value as bool, "boolean"))),
                     "character" => Some(Instruction::PushCharacter(parse_type!(value as char))),
-                    "register" => Some(Instruction::PushRegister(parse_register!(value))),
-                    _ => return Err(Error::InvalidInstruction),
+                    // Anything else was ruled out above
+                    _ => Some(Instruction::PushRegister(parse_register!(value))),
                 })
             }
             "pop" => {
-                next_word!(words => register);
-                Ok(Some(Instruction::Pop(if register == "_" {
+                next_word!(words => register, "register X, Y or _");
+                Ok(Some(Instruction::Pop(if register.text == "_" {
                     None
                 } else {
                     Some(parse_register!(register))
                 })))
             }
             "copy" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 Ok(Some(Instruction::Copy(parse_register!(register))))
             }
             "swap" => {
-                next_word!(words => register);
-                next_word!(words => index);
+                next_word!(words => register, "register X or Y");
+                next_word!(words => index, "stack index");
                 Ok(Some(Instruction::Swap(
                     parse_register!(register),
-                    parse_type!(index as usize)
+                    parse_type!(index as usize, "stack index")
                 )))
             }
             "length" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 Ok(Some(Instruction::Length(parse_register!(register))))
             }
             "branch" => {
-                next_word!(words => label_name);
-                let Some(index) = labels.get(label_name) else {
-                    return Err(Error::MissingLabel);
-                };
-                Ok(Some(Instruction::Branch(*index)))
+                next_word!(words => label_name, "label");
+                Ok(Some(Instruction::Branch(parse_label!(labels, label_name))))
             }
             "goto" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 Ok(Some(Instruction::Goto(parse_register!(register))))
             }
             "call" => {
-                next_word!(words => label_name);
-                let Some(index) = labels.get(label_name) else {
-                    return Err(Error::MissingLabel);
-                };
-                Ok(Some(Instruction::Call(*index)))
+                next_word!(words => label_name, "label");
+                Ok(Some(Instruction::Call(parse_label!(labels, label_name))))
             }
             "return" => Ok(Some(Instruction::Return)),
             "compare" => {
-                next_word!(words => comparison_mode);
-                let comparison = match comparison_mode {
+                next_word!(words => comparison_mode, "comparison equal, unequal, greater or less");
+                let comparison = match comparison_mode.text {
                     "equal" => Some(Ordering::Equal),
                     "unequal" => None,
                     "greater" => Some(Ordering::Greater),
                     "less" => Some(Ordering::Less),
-                    _ => return Err(Error::InvalidInstruction),
+                    _ => expected!(comparison_mode, "comparison equal, unequal, greater or less"),
                 };
                 Ok(Some(Instruction::Compare(comparison)))
             }
@@ -162,100 +245,135 @@ impl Instruction {
             "divide" => Ok(Some(Instruction::Divide)),
             "modulo" => Ok(Some(Instruction::Modulo)),
             "negate" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 Ok(Some(Instruction::Negate(parse_register!(register))))
             }
             "and" => Ok(Some(Instruction::And)),
             "or" => Ok(Some(Instruction::Or)),
             "xor" => Ok(Some(Instruction::Xor)),
             "not" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 Ok(Some(Instruction::Not(parse_register!(register))))
             }
             "shift" => Ok(Some(Instruction::Shift)),
             "rotate" => Ok(Some(Instruction::Rotate)),
             "cast" => {
-                next_word!(words => ty);
-                next_word!(words => register);
-                let ty = Type::from_str(ty).map_err(|_| Error::InvalidInstruction)?;
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Cast(ty, register)))
             }
             "reinterpret" => {
-                next_word!(words => ty);
-                next_word!(words => register);
-                let ty = Type::from_str(ty).map_err(|_| Error::InvalidInstruction)?;
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let name = ty;
+                let ty = parse_type_name!(ty);
                 if !matches!(ty, Type::Integer | Type::Float) {
-                    return Err(Error::InvalidInstruction);
+                    expected!(name, "type integer or float");
                 }
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Reinterpret(ty, register)))
             }
             "input" => {
-                next_word!(words => ty);
-                next_word!(words => register);
-                let ty = Type::from_str(ty).map_err(|_| Error::InvalidInstruction)?;
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Input(ty, register)))
             }
             "read" => {
-                next_word!(words => ty);
-                next_word!(words => register);
-                let ty = Type::from_str(ty).map_err(|_| Error::InvalidInstruction)?;
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Read(ty, register)))
             }
+            "input?" => {
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
+                let register = parse_register!(register);
+                Ok(Some(Instruction::TryInput(ty, register)))
+            }
+            "read?" => {
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
+                let register = parse_register!(register);
+                Ok(Some(Instruction::TryRead(ty, register)))
+            }
             "output" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Output(register)))
             }
             "write" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Write(register)))
             }
             "random" => {
-                next_word!(words => ty);
-                next_word!(words => register);
-                let ty = Type::from_str(ty).map_err(|_| Error::InvalidInstruction)?;
+                next_word!(words => ty, "type name");
+                next_word!(words => register, "register X or Y");
+                let ty = parse_type_name!(ty);
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Random(ty, register)))
             }
             "break" => Ok(Some(Instruction::Break)),
             "drop" => {
-                next_word!(words => register);
+                next_word!(words => register, "register X or Y");
                 let register = parse_register!(register);
                 Ok(Some(Instruction::Drop(register)))
             }
             "jump" => {
-                next_word!(words => label_name);
-                let Some(index) = labels.get(label_name) else {
-                    return Err(Error::MissingLabel);
-                };
-                Ok(Some(Instruction::Jump(*index)))
+                next_word!(words => label_name, "label");
+                Ok(Some(Instruction::Jump(parse_label!(labels, label_name))))
             }
             "debug" => Ok(Some(Instruction::Debug)),
             // Comment
-            line if line.starts_with('*') => Ok(None),
-            _ => Err(Error::InvalidInstruction),
+            name if name.starts_with('*') => Ok(None),
+            _ => Err(instruction_name.error(ParseErrorKind::UnknownInstruction)),
         }
     }
 }
 
-/// Parse an entire program from a string.
-/// Returns a vector of tuples of an instruction and what line it's on.
-/// Returns an error in case of a parsing failure.
-pub fn parse_file(file: impl AsRef<str>) -> Result<Vec<(usize, Instruction)>, (usize, Error)> {
-    let file = file.as_ref();
+/// Find all labels in a file, and the instruction indices they point to.
+/// Duplicate and malformed labels are collected as errors.
+fn find_labels(file: &str) -> (HashMap<&str, usize>, Vec<ParseError>) {
     let mut labels = HashMap::new();
-    // Need to do two iterations, one for labels
+    // Where each label was first defined, for reporting duplicates
+    let mut definitions = HashMap::new();
+    let mut errors = Vec::new();
     // A line doesn't necessarily map to an instruction,
     // so we can't enumerate for the jump indices
     let mut index = 0;
-    for line in file.lines() {
+    for (line_number, line) in file.lines().enumerate() {
         if !(line.starts_with(|c: char| c.is_ascii_whitespace())) {
-            labels.insert(line, index);
+            // Blank lines and comments aren't labels
+            if line.is_empty() || line.starts_with('*') {
+                continue;
+            }
+            let error = |kind| ParseError {
+                line: line_number,
+                columns: 0..line.len(),
+                token: line.to_string(),
+                kind,
+            };
+            // Still define the first word, so jumps to it don't error too
+            let name = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+                Some((name, _)) => {
+                    errors.push(error(ParseErrorKind::InvalidLabel));
+                    name
+                }
+                None => line,
+            };
+            if let Some(first) = definitions.get(name) {
+                errors.push(error(ParseErrorKind::DuplicateLabel(name.to_string(), *first)));
+                continue;
+            }
+            definitions.insert(name, line_number);
+            labels.insert(name, index);
             continue;
         }
         let mut words = line.trim().split_ascii_whitespace();
@@ -266,19 +384,65 @@ pub fn parse_file(file: impl AsRef<str>) -> Result<Vec<(usize, Instruction)>, (u
             }
         }
     }
+    (labels, errors)
+}
+
+/// Parse every instruction in a file, given its labels.
+/// Lines that fail to parse are skipped, and their errors are collected.
+fn parse_instructions(
+    file: &str,
+    labels: &HashMap<&str, usize>,
+) -> (Vec<(usize, Instruction)>, Vec<ParseError>) {
     let mut instructions = Vec::new();
-    // Now for the actual parsing
+    let mut errors = Vec::new();
     for (index, line) in file.lines().enumerate() {
         // We already handled labels
         if !line.starts_with(|c: char| c.is_ascii_whitespace()) {
             continue;
         }
-        // Option<T> is an iterator!
-        instructions.extend(
-            Instruction::parse(line, &labels)
-            .map_err(|err| (index, err))?
-            .map(|v| (index, v))
-        )
+        match Instruction::parse(line, labels) {
+            // Option<T> is an iterator!
+            Ok(instruction) => instructions.extend(instruction.map(|v| (index, v))),
+            Err(err) => errors.push(ParseError { line: index, ..err }),
+        }
+    }
+    (instructions, errors)
+}
+
+/// Parse an entire program from a string.
+/// Returns a vector of tuples of an instruction and what line it's on.
+/// Returns the first error in case of a parsing failure.
+pub fn parse_file(file: impl AsRef<str>) -> Result<Vec<(usize, Instruction)>, ParseError> {
+    Program::parse(file).map(|program| program.instructions)
+}
+
+impl Program {
+    /// Parse an entire program from a string.
+    /// Returns the first error in case of a parsing failure.
+    pub fn parse(file: impl AsRef<str>) -> Result<Program, ParseError> {
+        Self::parse_all(file).map_err(|mut errors| errors.swap_remove(0))
+    }
+
+    /// Parse an entire program from a string.
+    /// In case of a parsing failure, parsing carries on at the next line,
+    /// and every error is returned in the order they appear.
+    pub fn parse_all(file: impl AsRef<str>) -> Result<Program, Vec<ParseError>> {
+        let file = file.as_ref();
+        // Need to do two iterations, one for labels
+        let (labels, mut errors) = find_labels(file);
+        let (instructions, instruction_errors) = parse_instructions(file, &labels);
+        errors.extend(instruction_errors);
+        // Both passes go in order, but they need to be merged
+        errors.sort_by_key(|err| err.line);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Program {
+            instructions,
+            labels: labels
+                .into_iter()
+                .map(|(name, index)| (name.to_string(), index))
+                .collect(),
+        })
     }
-    Ok(instructions)
 }
@@ -0,0 +1,205 @@
+use crate::observer::{JumpKind, Observer, State};
+use crate::structures::*;
+use crate::trace::Trace;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// The name used for instructions before the first label, and for the outermost frame.
+pub const START_REGION: &str = "<start>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How much time was spent within a label's region.
+/// A region covers every instruction from its label up to the next one.
+pub struct Region {
+    /// The name of the label, or [`START_REGION`].
+    pub name: String,
+    /// The index of the first instruction in the region.
+    pub start: usize,
+    /// How many instructions were executed within the region.
+    pub executions: u64,
+    /// How long executing the region's instructions took in total.
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone)]
+/// An observer that counts how often every instruction is executed, and times label regions.
+pub struct Profiler {
+    executions: Vec<u64>,
+    regions: Vec<Region>,
+    /// The region each instruction is in.
+    region_of: Vec<usize>,
+    /// The regions called into, from the outermost one.
+    frames: Vec<usize>,
+    /// How many instructions were executed with each stack of frames.
+    folded: BTreeMap<Vec<usize>, u64>,
+    /// How many instructions were executed with the current frames since they last changed,
+    /// which aren't in `folded` yet, so the frames are only copied when they change.
+    unfolded: u64,
+    /// A call or return made by the instruction being executed,
+    /// which only applies once it's counted.
+    pending: Option<(JumpKind, usize)>,
+    started: Option<Instant>,
+}
+
+impl Profiler {
+    /// Create a profiler for a program.
+    pub fn new(program: &Program) -> Self {
+        let mut starts: Vec<(usize, &str)> = program
+            .labels()
+            .iter()
+            .map(|(name, &index)| (index, name.as_str()))
+            .collect();
+        // Sorting by name too keeps the first name alphabetically, like Program::enclosing_label
+        starts.sort_unstable();
+        starts.dedup_by_key(|(index, _)| *index);
+        if starts.first().is_none_or(|(index, _)| *index > 0) {
+            starts.insert(0, (0, START_REGION));
+        }
+        let regions: Vec<Region> = starts
+            .iter()
+            .map(|&(start, name)| Region {
+                name: name.to_string(),
+                start,
+                executions: 0,
+                time: Duration::ZERO,
+            })
+            .collect();
+        let region_of = (0..program.len())
+            .map(|index| regions.partition_point(|region| region.start <= index) - 1)
+            .collect();
+        Self {
+            executions: vec![0; program.len()],
+            regions,
+            region_of,
+            frames: vec![0],
+            folded: BTreeMap::new(),
+            unfolded: 0,
+            pending: None,
+            started: None,
+        }
+    }
+
+    /// Get how many times each instruction was executed, by index.
+    pub fn executions(&self) -> &[u64] {
+        &self.executions
+    }
+
+    /// Get every region, in the order they appear in the program.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Count the instructions executed with the current frames, before they change.
+    fn fold(&mut self) {
+        let count = std::mem::take(&mut self.unfolded);
+        *self.folded.entry(self.frames.clone()).or_default() += count;
+    }
+
+    /// Get the region an instruction is in.
+    fn region(&self, index: usize) -> usize {
+        // Calls may jump outside of the program, which halts it
+        self.region_of
+            .get(index)
+            .copied()
+            .unwrap_or(self.regions.len() - 1)
+    }
+
+    /// Write the amount of instructions executed for every stack of calls,
+    /// in the folded format used by flamegraph tools, like `<start>;LOOP;PRINT 42`.
+    /// The outermost frame is always [`START_REGION`].
+    pub fn folded(&self) -> String {
+        let mut counts = self.folded.clone();
+        if self.unfolded > 0 {
+            *counts.entry(self.frames.clone()).or_default() += self.unfolded;
+        }
+        let mut folded = String::new();
+        for (frames, count) in &counts {
+            let names: Vec<&str> = frames
+                .iter()
+                .enumerate()
+                .map(|(depth, &region)| match depth {
+                    0 => START_REGION,
+                    _ => &self.regions[region].name,
+                })
+                .collect();
+            let _ = writeln!(folded, "{} {count}", names.join(";"));
+        }
+        folded
+    }
+
+    /// Write a report of the hottest instructions, up to a limit, and of all executed regions,
+    /// with the most executed first. Instructions are shown with the source line they're on.
+    pub fn report(&self, program: &Program, source: &str, limit: usize) -> String {
+        let total: u64 = self.executions.iter().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        let mut report = format!("{total} instructions executed.\n\nHottest instructions:\n");
+        let mut hottest: Vec<usize> = (0..self.executions.len())
+            .filter(|&index| self.executions[index] > 0)
+            .collect();
+        // Sorting is stable, so ties stay in program order
+        hottest.sort_by_key(|&index| std::cmp::Reverse(self.executions[index]));
+        for &index in hottest.iter().take(limit) {
+            let (line, _) = program.instructions()[index];
+            let text = source.lines().nth(line).unwrap_or_default().trim();
+            let _ = writeln!(
+                report,
+                "{:>12} {:>6.2}%  line {:<5} {text}",
+                self.executions[index],
+                percent(self.executions[index]),
+                line + 1,
+            );
+        }
+        report.push_str("\nRegions:\n");
+        let mut regions: Vec<&Region> = self
+            .regions
+            .iter()
+            .filter(|region| region.executions > 0)
+            .collect();
+        regions.sort_by_key(|region| std::cmp::Reverse(region.executions));
+        for region in regions {
+            let line = program.get(region.start).map_or(0, |(line, _)| line + 1);
+            let _ = writeln!(
+                report,
+                "{:>12} {:>6.2}% {:>10.3?}  {} (line {line})",
+                region.executions,
+                percent(region.executions),
+                region.time,
+                region.name,
+            );
+        }
+        report
+    }
+}
+
+impl Observer for Profiler {
+    fn before(&mut self, _: &State) {
+        self.started = Some(Instant::now());
+    }
+
+    fn after(&mut self, trace: &Trace) {
+        let region = self.region(trace.index);
+        self.executions[trace.index] += 1;
+        self.regions[region].executions += 1;
+        if let Some(started) = self.started.take() {
+            self.regions[region].time += started.elapsed();
+        }
+        self.unfolded += 1;
+        match self.pending.take() {
+            Some((JumpKind::Call, to)) => {
+                self.fold();
+                self.frames.push(self.region(to));
+            }
+            // Returning from the outermost frame has nowhere to go
+            Some((JumpKind::Return, _)) if self.frames.len() > 1 => {
+                self.fold();
+                self.frames.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn jumped(&mut self, kind: JumpKind, _: usize, to: usize) {
+        self.pending = Some((kind, to));
+    }
+}
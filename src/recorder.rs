@@ -0,0 +1,204 @@
+use crate::io::Io;
+use crate::machine::{Machine, StepResult};
+use crate::structures::*;
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq)]
+/// The parts of a machine that an instruction may change.
+pub struct PartialState {
+    /// The index of the next instruction.
+    pub pc: usize,
+    /// How many instructions had been executed.
+    pub steps: u64,
+    /// The value in the X register.
+    pub x: Option<Value>,
+    /// The value in the Y register.
+    pub y: Option<Value>,
+    /// The values on the stack above the untouched part of it, from the bottom up.
+    pub stack: Vec<Value>,
+    /// How the machine stopped, if it did.
+    pub outcome: Option<Outcome>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The changes a single instruction made to a machine.
+pub struct Delta {
+    /// How many values at the bottom of the stack were left untouched.
+    pub stack_base: usize,
+    /// The state before the instruction executed.
+    pub before: PartialState,
+    /// The state after the instruction executed.
+    pub after: PartialState,
+    /// What executing the instruction returned.
+    pub result: StepResult,
+}
+
+impl Delta {
+    /// Check whether this delta changed a register or an absolute stack index.
+    pub fn changed(&self, watch: Watch) -> bool {
+        match watch {
+            Watch::X => self.before.x != self.after.x,
+            Watch::Y => self.before.y != self.after.y,
+            Watch::Stack(index) => {
+                let Some(offset) = index.checked_sub(self.stack_base) else {
+                    return false;
+                };
+                self.before.stack.get(offset) != self.after.stack.get(offset)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Something a recorder can look for changes in.
+pub enum Watch {
+    /// The X register.
+    X,
+    /// The Y register.
+    Y,
+    /// A stack slot, counting from the bottom of the stack.
+    Stack(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// Records the changes every step makes to a machine, so it can be stepped backwards.
+///
+/// Stepping forwards through recorded history replays the recorded changes
+/// instead of executing instructions again, so input isn't read and output isn't
+/// written twice, and random values stay the same.
+/// Only registers, the stack and the instruction pointer are rewound.
+pub struct Recorder {
+    deltas: Vec<Delta>,
+    position: usize,
+    /// The maximum amount of steps to remember. The oldest are forgotten first.
+    pub limit: Option<usize>,
+}
+
+impl Recorder {
+    /// Create an empty recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get every recorded step, in order.
+    pub fn deltas(&self) -> &[Delta] {
+        &self.deltas
+    }
+
+    /// Get how many recorded steps are currently applied to the machine.
+    /// This is less than the amount of recorded steps after stepping backwards.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Forget every step after the current position.
+    /// This should be done whenever the machine is changed from outside the recorder,
+    /// as replaying those steps would no longer be accurate.
+    pub fn forget_future(&mut self) {
+        self.deltas.truncate(self.position);
+    }
+
+    /// Step the machine forwards, replaying a recorded step if there is one.
+    pub fn step<R: Rng, I: Io>(&mut self, machine: &mut Machine<R, I>) -> StepResult {
+        if let Some(delta) = self.deltas.get(self.position) {
+            apply(machine, delta.stack_base, &delta.after);
+            self.position += 1;
+            return delta.result.clone();
+        }
+        if machine.is_stopped() {
+            return machine.step();
+        }
+        // Only swaps can reach further down the stack than the top value
+        let reach = match machine.current() {
+            Some((_, Instruction::Swap(_, index))) => index + 1,
+            _ => 1,
+        };
+        let stack_base = machine.interpreter.stack.len().saturating_sub(reach);
+        let before = capture(machine, stack_base);
+        let result = machine.step();
+        let after = capture(machine, stack_base);
+        self.deltas.push(Delta {
+            stack_base,
+            before,
+            after,
+            result: result.clone(),
+        });
+        self.position += 1;
+        if let Some(limit) = self.limit {
+            if self.deltas.len() > limit {
+                let excess = self.deltas.len() - limit;
+                self.deltas.drain(..excess);
+                self.position -= excess;
+            }
+        }
+        result
+    }
+
+    /// Step the machine backwards, undoing the last recorded step.
+    /// Returns false if there are no recorded steps to undo.
+    pub fn step_back<R: Rng, I: Io>(&mut self, machine: &mut Machine<R, I>) -> bool {
+        let Some(position) = self.position.checked_sub(1) else {
+            return false;
+        };
+        let delta = &self.deltas[position];
+        apply(machine, delta.stack_base, &delta.before);
+        self.position = position;
+        true
+    }
+
+    /// Move the machine to any recorded position, replaying or undoing steps to get there.
+    /// The position is clamped to the recorded history.
+    pub fn seek<R: Rng, I: Io>(&mut self, machine: &mut Machine<R, I>, position: usize) {
+        let position = position.min(self.deltas.len());
+        while self.position > position {
+            self.step_back(machine);
+        }
+        while self.position < position {
+            self.step(machine);
+        }
+    }
+
+    /// Step backwards until right before the last step that changed something.
+    /// Returns the instruction index of that step, or None (without moving)
+    /// if it never changed in the recorded history.
+    pub fn last_change<R: Rng, I: Io>(
+        &mut self,
+        machine: &mut Machine<R, I>,
+        watch: Watch,
+    ) -> Option<usize> {
+        let position = self.deltas[..self.position]
+            .iter()
+            .rposition(|delta| delta.changed(watch))?;
+        self.seek(machine, position);
+        Some(self.deltas[position].before.pc)
+    }
+}
+
+/// Copy the parts of a machine an instruction may change.
+fn capture<R: Rng, I: Io>(machine: &Machine<R, I>, stack_base: usize) -> PartialState {
+    let interpreter = &*machine.interpreter;
+    PartialState {
+        pc: machine.pc,
+        steps: machine.steps,
+        x: interpreter.x.clone(),
+        y: interpreter.y.clone(),
+        stack: interpreter
+            .stack
+            .get(stack_base..)
+            .unwrap_or_default()
+            .to_vec(),
+        outcome: machine.outcome.clone(),
+    }
+}
+
+/// Put a machine into a recorded state.
+fn apply<R: Rng, I: Io>(machine: &mut Machine<R, I>, stack_base: usize, state: &PartialState) {
+    machine.pc = state.pc;
+    machine.steps = state.steps;
+    machine.outcome.clone_from(&state.outcome);
+    let interpreter = &mut *machine.interpreter;
+    interpreter.x.clone_from(&state.x);
+    interpreter.y.clone_from(&state.y);
+    interpreter.stack.truncate(stack_base);
+    interpreter.stack.extend_from_slice(&state.stack);
+}
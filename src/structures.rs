@@ -1,7 +1,10 @@
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use crate::io::{Io, Stdio};
+use rand::rngs::{StdRng, ThreadRng};
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::io;
+use std::ops::Range;
 
 // Does not implement Copy on purpose, so that move semantics (which apply in the language)
 // are easy to implement
@@ -93,6 +96,15 @@ pub enum Register {
     Y,
 }
 
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::X => write!(f, "X"),
+            Register::Y => write!(f, "Y"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 /// A singular instruction.
 pub enum Instruction {
@@ -172,13 +184,19 @@ pub enum Instruction {
     /// without touching the bits.
     Reinterpret(Type, Register),
 
-    /// Grabs a value from stdin as text, and puts it into this register.
+    /// Grabs a value from the input as text, and puts it into this register.
     Input(Type, Register),
-    /// Grabs a value from stdin as bytes, and puts it into this register.
+    /// Grabs a value from the input as bytes, and puts it into this register.
     Read(Type, Register),
-    /// Outputs the value in the register to stdout as text.
+    /// Grabs a value from the input as text, and puts it into this register,
+    /// pushing whether there was one. Empties the register if the input has ended.
+    TryInput(Type, Register),
+    /// Grabs a value from the input as bytes, and puts it into this register,
+    /// pushing whether there was one. Empties the register if the input has ended.
+    TryRead(Type, Register),
+    /// Writes the value in the register to the output as text.
     Output(Register),
-    /// Outputs the value in the register to stdout as bytes.
+    /// Writes the value in the register to the output as bytes.
     Write(Register),
 
     /// Puts a random valid value of the specified type into the register.
@@ -195,69 +213,470 @@ pub enum Instruction {
     Debug
 }
 
+/// Writes instructions the way they're written in source code,
+/// except that labels are written as the instruction index they point to, like `jump #3`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        match self {
+            PushInteger(i) => write!(f, "push integer {i}"),
+            // Debug formatting keeps the decimal point, and writes infinities as `inf`
+            PushFloat(d) => write!(f, "push float {d:?}"),
+            PushBoolean(b) => write!(f, "push boolean {b}"),
+            PushCharacter(c) if c.is_ascii_graphic() => {
+                write!(f, "push character '{}'", *c as char)
+            }
+            PushCharacter(c) => write!(f, "push character #{c:02X}"),
+            PushRegister(reg) => write!(f, "push register {reg}"),
+            Pop(Some(reg)) => write!(f, "pop {reg}"),
+            Pop(None) => write!(f, "pop _"),
+            Copy(reg) => write!(f, "copy {reg}"),
+            Length(reg) => write!(f, "length {reg}"),
+            Branch(index) => write!(f, "branch #{index}"),
+            Compare(ordering) => write!(
+                f,
+                "compare {}",
+                match ordering {
+                    Some(std::cmp::Ordering::Equal) => "equal",
+                    Some(std::cmp::Ordering::Greater) => "greater",
+                    Some(std::cmp::Ordering::Less) => "less",
+                    None => "unequal",
+                }
+            ),
+            Add => write!(f, "add"),
+            Subtract => write!(f, "subtract"),
+            Multiply => write!(f, "multiply"),
+            Divide => write!(f, "divide"),
+            Modulo => write!(f, "modulo"),
+            Negate(reg) => write!(f, "negate {reg}"),
+            And => write!(f, "and"),
+            Or => write!(f, "or"),
+            Xor => write!(f, "xor"),
+            Not(reg) => write!(f, "not {reg}"),
+            Shift => write!(f, "shift"),
+            Rotate => write!(f, "rotate"),
+            Cast(ty, reg) => write!(f, "cast {ty} {reg}"),
+            Reinterpret(ty, reg) => write!(f, "reinterpret {ty} {reg}"),
+            Input(ty, reg) => write!(f, "input {ty} {reg}"),
+            Read(ty, reg) => write!(f, "read {ty} {reg}"),
+            TryInput(ty, reg) => write!(f, "input? {ty} {reg}"),
+            TryRead(ty, reg) => write!(f, "read? {ty} {reg}"),
+            Output(reg) => write!(f, "output {reg}"),
+            Write(reg) => write!(f, "write {reg}"),
+            Random(ty, reg) => write!(f, "random {ty} {reg}"),
+            Break => write!(f, "break"),
+            Drop(reg) => write!(f, "drop {reg}"),
+            Goto(reg) => write!(f, "goto {reg}"),
+            Jump(index) => write!(f, "jump #{index}"),
+            Call(index) => write!(f, "call #{index}"),
+            Return => write!(f, "return"),
+            Swap(reg, index) => write!(f, "swap {reg} {index}"),
+            Debug => write!(f, "debug"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// What to do when input text can't be parsed as the type being asked for.
+pub enum InputRetry {
+    /// Keep reading lines until one can be parsed.
+    #[default]
+    Forever,
+    /// Read up to this many more lines, then fail with [`Error::InvalidInput`].
+    Times(usize),
+    /// Fail with [`Error::InvalidInput`] right away.
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// A parsed program, ready to be run.
+pub struct Program {
+    pub(crate) instructions: Vec<(usize, Instruction)>,
+    pub(crate) labels: HashMap<String, usize>,
+}
+
+impl Program {
+    /// Get the instructions of this program, alongside the line they're on.
+    pub fn instructions(&self) -> &[(usize, Instruction)] {
+        &self.instructions
+    }
+
+    /// Get the instruction at an index, alongside the line it's on.
+    pub fn get(&self, index: usize) -> Option<(usize, Instruction)> {
+        self.instructions.get(index).copied()
+    }
+
+    /// Get the instruction index a label points to.
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// Get the name of a label pointing at an instruction index.
+    /// When several labels point at it, the first one alphabetically is used.
+    pub fn label_at(&self, index: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, &at)| at == index)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    /// Write an instruction like it's written in source code,
+    /// using the name of the label it jumps to instead of its index if there is one.
+    pub fn source_text(&self, instruction: Instruction) -> String {
+        let text = instruction.to_string();
+        let label = match instruction {
+            Instruction::Jump(to) | Instruction::Branch(to) | Instruction::Call(to) => {
+                self.label_at(to)
+            }
+            _ => None,
+        };
+        match (label, text.split_once(' ')) {
+            (Some(label), Some((name, _))) => format!("{name} {label}"),
+            _ => text,
+        }
+    }
+
+    /// Get all labels in this program, and the instruction indices they point to.
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+
+    /// Get the index of the first instruction on or after a line, counting from 0.
+    pub fn index_at_line(&self, line: usize) -> Option<usize> {
+        self.instructions
+            .iter()
+            .position(|(instruction_line, _)| *instruction_line >= line)
+    }
+
+    /// Get the label whose region an instruction index is in,
+    /// which is the closest label pointing at or before it.
+    /// When several labels point at the same index, the first one alphabetically is used.
+    pub fn enclosing_label(&self, index: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, &at)| at <= index)
+            .max_by(|(a, a_at), (b, b_at)| a_at.cmp(b_at).then_with(|| b.cmp(a)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Get the amount of instructions in this program.
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Check whether this program has no instructions.
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 /// An instance of an interpreter.
-pub struct Interpreter<R: Rng> {
+pub struct Interpreter<R: Rng, I: Io = Stdio> {
     pub x: Option<Value>,
     pub y: Option<Value>,
     pub stack: Vec<Value>,
+    /// Where input is read from, and output is written to.
+    pub io: I,
+    /// The maximum amount of instructions a machine may execute
+    /// before failing with [`Error::FuelExhausted`].
+    pub max_steps: Option<u64>,
+    /// The maximum amount of values the stack may hold
+    /// before pushing fails with [`Error::StackOverflow`].
+    pub max_stack: Option<usize>,
+    /// How many times input text that can't be parsed is asked for again.
+    pub input_retry: InputRetry,
     pub(crate) rng: R,
+    /// Input that was read, but not used yet.
+    /// This is kept across instructions, so reads don't lose data.
+    pub(crate) input_buffer: Vec<u8>,
 }
 
-impl Default for Interpreter<ThreadRng> {
-    fn default() -> Self {
+impl<R: Rng> Interpreter<R> {
+    /// Create an interpreter that generates random values with the given RNG,
+    /// using stdin and stdout for input and output.
+    pub fn with_rng(rng: R) -> Self {
         Self {
             x: None,
             y: None,
             stack: Vec::new(),
-            rng: rand::thread_rng(),
+            io: Stdio,
+            max_steps: None,
+            max_stack: None,
+            input_retry: InputRetry::Forever,
+            rng,
+            input_buffer: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+impl<R: Rng, I: Io> Interpreter<R, I> {
+    /// Replace where this interpreter reads input from and writes output to.
+    /// Any input that was read but not used yet is kept.
+    pub fn with_io<J: Io>(self, io: J) -> Interpreter<R, J> {
+        Interpreter {
+            x: self.x,
+            y: self.y,
+            stack: self.stack,
+            io,
+            max_steps: self.max_steps,
+            max_stack: self.max_stack,
+            input_retry: self.input_retry,
+            rng: self.rng,
+            input_buffer: self.input_buffer,
+        }
+    }
+}
+
+impl Interpreter<StdRng> {
+    /// Create an interpreter with a seeded RNG,
+    /// so that random values are the same across runs.
+    pub fn seeded(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl Default for Interpreter<ThreadRng> {
+    fn default() -> Self {
+        Self::with_rng(rand::thread_rng())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// A reason why execution of an instruction failed.
 pub enum Error {
     /// Attempted to divide integral values by zero.
     DivideByZero,
-    /// Failed to read from stdin.
-    ReadFailed,
-    /// Failed to write to stdout.
-    WriteFailed,
+    /// Failed to read from the input.
+    ReadFailed(io::ErrorKind),
+    /// Tried to read from the input, but it had nothing left.
+    EndOfInput,
+    /// Failed to write to the output.
+    WriteFailed(io::ErrorKind),
     /// The type of the value was invalid for the instruction.
     InvalidType(Type),
     /// Mismatched types for an instruction.
     MismatchedTypes(Type, Type),
     /// The stack was accessed at an invalid index.
     StackOutOfBounds(i64),
-    /// Encountered an invalid instruction while parsing.
-    InvalidInstruction,
     /// One or more registers targeted were empty.
     EmptyRegister(Register),
-    /// One or more labels wasn't found.
-    MissingLabel,
+    /// The instruction limit was reached after executing this many instructions.
+    FuelExhausted(u64),
+    /// A value was pushed onto a stack already holding this many values.
+    StackOverflow(usize),
+    /// Input text couldn't be parsed as the type, and no retries were left.
+    /// Holds the rejected text.
+    InvalidInput(Type, String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Error::DivideByZero => write!(f, "attempted to divide by integral zero"),
-            Error::ReadFailed => write!(f, "failed to read from stdin"),
-            Error::WriteFailed => write!(f, "failed to write to stdout"),
+            Error::ReadFailed(kind) => write!(f, "failed to read from the input ({kind})"),
+            Error::EndOfInput => write!(f, "reached the end of the input"),
+            Error::WriteFailed(kind) => write!(f, "failed to write to the output ({kind})"),
             Error::InvalidType(ty) => write!(f, "failed to execute with invalid type {ty}"),
             Error::MismatchedTypes(ty1, ty2) => {
                 write!(f, "failed to operate with types {ty1} and {ty2}")
             }
             Error::StackOutOfBounds(index) => write!(f, "failed to access stack value #{index}"),
-            Error::InvalidInstruction => write!(f, "encountered an invalid instruction"),
             Error::EmptyRegister(reg) => write!(f, "encountered an unexpected empty register {reg:?}"),
-            Error::MissingLabel => write!(f, "could not find a matching label"),
+            Error::FuelExhausted(steps) => {
+                write!(f, "ran out of fuel after executing {steps} instructions")
+            }
+            Error::StackOverflow(limit) => {
+                write!(f, "overflowed the stack past its limit of {limit} values")
+            }
+            Error::InvalidInput(ty, text) => write!(f, "could not parse input {text:?} as {ty}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A reason why parsing a line failed.
+pub enum ParseErrorKind {
+    /// The instruction name wasn't recognized.
+    UnknownInstruction,
+    /// A field was invalid or missing. Holds a description of what was expected.
+    Expected(&'static str),
+    /// A label that was jumped to doesn't exist.
+    MissingLabel(String),
+    /// A label was defined more than once.
+    /// Holds the label, and the line it was first defined on.
+    DuplicateLabel(String, usize),
+    /// A label had whitespace in its name.
+    InvalidLabel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An error encountered while parsing, and where it happened.
+pub struct ParseError {
+    /// The line the error is on, counting from 0.
+    pub line: usize,
+    /// The range of bytes within the line that the error points to.
+    /// This is empty if something was missing at the end of the line.
+    pub columns: Range<usize>,
+    /// The offending token. Empty if something was missing.
+    pub token: String,
+    /// Why parsing failed.
+    pub kind: ParseErrorKind,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnknownInstruction => write!(f, "unknown instruction `{}`", self.token),
+            ParseErrorKind::Expected(what) if self.token.is_empty() => {
+                write!(f, "expected {what}, found end of line")
+            }
+            ParseErrorKind::Expected(what) => write!(f, "expected {what}, found `{}`", self.token),
+            ParseErrorKind::MissingLabel(label) => write!(f, "could not find label `{label}`"),
+            ParseErrorKind::DuplicateLabel(label, first) => write!(
+                f,
+                "label `{label}` was already defined at line {}",
+                first + 1
+            ),
+            ParseErrorKind::InvalidLabel => {
+                write!(f, "label `{}` must not contain whitespace", self.token)
+            }
+        }
+    }
+}
+
+impl ParseError {
+    /// Render this error with the line it's on, underlining where it happened.
+    pub fn render(&self, source: &str) -> String {
+        let text = source.lines().nth(self.line).unwrap_or_default();
+        let number = (self.line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        // Keep tabs, so the underline lines up with the source
+        let padding: String = text
+            .get(..self.columns.start)
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat(self.columns.len().max(1));
+        format!(
+            "{self}\n{gutter}--> line {number}, column {}\n{gutter} |\n{number} | {text}\n{gutter} | {padding}{underline}",
+            self.columns.start + 1
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+/// Where execution should go after an instruction.
+pub enum Flow {
+    /// Continue to the next instruction.
+    Continue,
+    /// Jump to an instruction index.
+    Jump(usize),
+    /// Stop execution, as the program tried to leave its bounds.
+    Halt,
+    /// Stop execution, as a break instruction was executed.
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error that happened while running a program,
+/// alongside where it happened and the state of the interpreter before the instruction.
+pub struct RuntimeError {
+    /// Why the instruction failed.
+    pub error: Error,
+    /// The index of the instruction that failed.
+    pub index: usize,
+    /// The line the instruction is on, counting from 0.
+    pub line: usize,
+    /// The instruction that failed.
+    pub instruction: Instruction,
+    /// The instruction that failed, written like in the source code.
+    pub text: String,
+    /// The value in the X register before the instruction.
+    pub x: Option<Value>,
+    /// The value in the Y register before the instruction.
+    pub y: Option<Value>,
+    /// The values on top of the stack before the instruction, from the bottom up.
+    /// At most [`RuntimeError::STACK_SNAPSHOT`] values are kept.
+    pub stack_top: Vec<Value>,
+    /// The length of the whole stack before the instruction.
+    pub stack_length: usize,
+}
+
+impl RuntimeError {
+    /// How many values from the top of the stack are kept.
+    pub const STACK_SNAPSHOT: usize = 8;
+
+    /// Describe the failure like [`Display`] does,
+    /// also showing the source line of the instruction that failed.
+    pub fn render(&self, source: &str) -> String {
+        let text = source.lines().nth(self.line).unwrap_or_default().trim();
+        // The source goes between the first line and the state of the interpreter
+        let display = self.to_string();
+        let (header, state) = display.split_once('\n').unwrap_or((&display, ""));
+        let number = (self.line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        format!("{header}\n{gutter} |\n{number} | {text}\n{gutter} |\n{state}")
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let register = |value: &Option<Value>| match value {
+            Some(value) => format!("{value} ({})", value.get_type()),
+            None => "empty".to_string(),
+        };
+        writeln!(
+            f,
+            "{} at line {} (instruction #{}: {})",
+            self.error,
+            self.line + 1,
+            self.index,
+            self.text
+        )?;
+        writeln!(f, "X: {}\tY: {}", register(&self.x), register(&self.y))?;
+        write!(f, "Stack ({} values): [", self.stack_length)?;
+        if self.stack_length > self.stack_top.len() {
+            write!(f, "..., ")?;
+        }
+        for (i, value) in self.stack_top.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{value}")?;
         }
+        write!(f, "]")
     }
 }
 
+impl std::error::Error for Error {}
+
+impl std::error::Error for ParseError {}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The way a program stopped running.
+pub enum Outcome {
+    /// Execution went past the end of the program, or jumped outside of it.
+    Halted,
+    /// A break instruction was executed.
+    Break,
+    /// An instruction failed to execute.
+    Errored(RuntimeError),
+}
+
 impl From<io::Error> for Error {
-    fn from(_: io::Error) -> Self {
-        Self::ReadFailed
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::EndOfInput,
+            kind => Self::ReadFailed(kind),
+        }
     }
 }
@@ -0,0 +1,50 @@
+use crate::json::Json;
+use crate::structures::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What happened when a single instruction was executed, as given to trace hooks.
+pub struct Trace<'a> {
+    /// How many instructions were executed before this one.
+    pub step: u64,
+    /// The index of the instruction.
+    pub index: usize,
+    /// The line the instruction is on, counting from 0.
+    pub line: usize,
+    /// The instruction that was executed.
+    pub instruction: Instruction,
+    /// The value in the X register after executing.
+    pub x: &'a Option<Value>,
+    /// The value in the Y register after executing.
+    pub y: &'a Option<Value>,
+    /// The whole stack after executing, from the bottom up.
+    pub stack: &'a [Value],
+    /// Why the instruction failed, if it did.
+    pub error: Option<&'a Error>,
+}
+
+impl Trace<'_> {
+    /// Convert this trace of a program into a JSON object, with lines counted from 1.
+    /// Instructions are written like in source code, with the names of the labels they use.
+    /// The stack is only included in full if asked for, otherwise just its depth is.
+    pub fn to_json(&self, program: &Program, full_stack: bool) -> Json {
+        let mut entries = vec![
+            ("step", self.step.into()),
+            ("index", self.index.into()),
+            ("line", (self.line + 1).into()),
+            ("instruction", program.source_text(self.instruction).into()),
+            ("x", self.x.as_ref().into()),
+            ("y", self.y.as_ref().into()),
+            ("depth", self.stack.len().into()),
+        ];
+        if full_stack {
+            entries.push((
+                "stack",
+                Json::Array(self.stack.iter().map(Json::from).collect()),
+            ));
+        }
+        if let Some(error) = self.error {
+            entries.push(("error", error.to_string().into()));
+        }
+        Json::object(entries)
+    }
+}
@@ -0,0 +1,82 @@
+use pancake::{Block, Cfg, Edge, JumpKind, Program, Target};
+
+const PROGRAM: &str = "    push integer 3
+LOOP
+    call PRINT
+    push boolean true
+    branch LOOP
+    break
+PRINT
+    pop X
+    output X
+    return
+";
+
+fn edge(kind: Option<JumpKind>, target: Target) -> Edge {
+    Edge { kind, target }
+}
+
+#[test]
+fn blocks() {
+    let program = Program::parse_all(PROGRAM).unwrap();
+    let cfg = Cfg::new(program.instructions());
+    assert_eq!(
+        cfg.blocks(),
+        [
+            Block {
+                start: 0,
+                end: 1,
+                edges: vec![edge(None, Target::Block(1))],
+            },
+            Block {
+                start: 1,
+                end: 2,
+                edges: vec![edge(Some(JumpKind::Call), Target::Block(4))],
+            },
+            // Only reached by returning from the call
+            Block {
+                start: 2,
+                end: 4,
+                edges: vec![
+                    edge(Some(JumpKind::Branch), Target::Block(1)),
+                    edge(None, Target::Block(3)),
+                ],
+            },
+            Block {
+                start: 4,
+                end: 5,
+                edges: vec![],
+            },
+            Block {
+                start: 5,
+                end: 8,
+                edges: vec![edge(Some(JumpKind::Return), Target::Dynamic)],
+            },
+        ]
+    );
+    assert_eq!(cfg.block_of(6), Some(4));
+    assert_eq!(cfg.block_of(8), None);
+    assert_eq!(cfg.predecessors(1), [0, 2]);
+    assert_eq!(cfg.predecessors(2), []);
+}
+
+#[test]
+fn dot() {
+    let program = Program::parse_all("    jump END\nEND\n    goto X\n").unwrap();
+    let cfg = Cfg::new(program.instructions());
+    assert_eq!(
+        cfg.dot(&program),
+        r#"digraph cfg {
+    node [shape=box];
+    dynamic [shape=diamond, label="dynamic"];
+    b0 [label="line 1"];
+    b1 [label="END\nline 3"];
+    b0 -> b1 [label="jump"];
+    b1 -> dynamic [label="goto", style=dashed];
+}
+"#
+    );
+    let program = Program::parse_all("    push integer 1\n    pop X\nA\nB\n").unwrap();
+    let listing = Cfg::new(program.instructions()).listing(&program);
+    assert_eq!(listing, "block 0, lines 1-2\n    next -> exit\n");
+}
@@ -0,0 +1,201 @@
+use pancake::{check, Error, Program, Register, Shape, Type, ValueKind};
+
+/// Check a program, summarizing warnings as their line, whether they always fail, and the error.
+fn warnings(source: &str) -> Vec<(usize, bool, Error)> {
+    let program = Program::parse_all(source).unwrap();
+    check(&program)
+        .into_iter()
+        .map(|warning| (warning.line + 1, warning.always, warning.error))
+        .collect()
+}
+
+#[test]
+fn empty_registers() {
+    let source = "    push integer 1
+    pop X
+    output X
+    output X
+    push integer 2
+    pop Y
+    add
+    random boolean X
+    push register X
+    branch SKIP
+    pop X
+SKIP
+    negate X
+    break
+    output Y
+";
+    assert_eq!(
+        warnings(source),
+        [
+            (4, true, Error::EmptyRegister(Register::X)),
+            (7, true, Error::EmptyRegister(Register::X)),
+            // Only filled when the branch isn't taken
+            (13, false, Error::EmptyRegister(Register::X)),
+        ]
+    );
+    // The register that's always empty is the one reported
+    let source = "    input? integer X
+    pop _
+    add
+";
+    assert_eq!(
+        warnings(source),
+        [(3, true, Error::EmptyRegister(Register::Y))]
+    );
+}
+
+#[test]
+fn reading_and_calls() {
+    // Branching on whether a value was read tells whether the register is full
+    let source = "LOOP
+    read? character X
+    branch WRITE
+    output X
+    break
+WRITE
+    push integer 5
+    pop Y
+    call PRINT
+    output Y
+    jump LOOP
+PRINT
+    copy Y
+    output X
+    return
+";
+    assert_eq!(
+        warnings(source),
+        [
+            (4, true, Error::EmptyRegister(Register::X)),
+            // Returning goes back to after the call, where Y is still full
+        ]
+    );
+    // Returning to an index that a call didn't push may go anywhere, like gotos,
+    // with the stack it leaves
+    let source = "    push integer 3
+    return
+    break
+    output Y
+";
+    assert_eq!(
+        warnings(source),
+        [
+            (2, false, Error::StackOutOfBounds(0)),
+            (4, true, Error::EmptyRegister(Register::Y)),
+        ]
+    );
+    let source = "    push integer 3
+    pop X
+    goto X
+    output Y
+    push integer 1
+    pop Y
+    output Y
+";
+    // Gotos may go anywhere, even between filling a register and using it,
+    // and they leave the stack empty here
+    assert_eq!(
+        warnings(source),
+        [
+            (2, false, Error::StackOutOfBounds(0)),
+            (3, false, Error::EmptyRegister(Register::X)),
+            (4, true, Error::EmptyRegister(Register::Y)),
+            (6, false, Error::StackOutOfBounds(0)),
+            (7, false, Error::EmptyRegister(Register::Y)),
+        ]
+    );
+}
+
+#[test]
+fn types_and_depth() {
+    let source = "    push integer 1
+    pop X
+    push float 2.0
+    pop Y
+    add
+    push boolean true
+    pop X
+    push character 'a'
+    pop Y
+    compare less
+    push integer 1
+    branch END
+    pop _
+    swap X 2
+END
+    pop X
+    pop X
+    pop X
+";
+    assert_eq!(
+        warnings(source),
+        [
+            (5, true, Error::MismatchedTypes(Type::Integer, Type::Float)),
+            // Values of any types can be compared, so line 10 is fine
+            (12, true, Error::InvalidType(Type::Integer)),
+            // X is taken before the stack is, so that's the only way it fails
+            (14, true, Error::EmptyRegister(Register::X)),
+            (18, false, Error::StackOutOfBounds(0)),
+        ]
+    );
+}
+
+#[test]
+fn shapes() {
+    let source = "    input? integer X
+LOOP
+    push character 'a'
+    push boolean true
+    branch LOOP
+    cast integer X
+    length Y
+";
+    let program = Program::parse_all(source).unwrap();
+    let shapes = pancake::shapes(&program);
+    let value = |ty| Some(ValueKind::Value(ty));
+    // Loops that keep pushing have no limit on their depth
+    assert_eq!(
+        shapes[1],
+        Some(Shape {
+            x: value(Type::Integer),
+            y: None,
+            top: vec![None],
+            min_depth: 1,
+            max_depth: None,
+        })
+    );
+    assert_eq!(
+        shapes[3].as_ref().unwrap().top,
+        [value(Type::Boolean), value(Type::Character), None]
+    );
+    // Casting to the type it already has pushes that it succeeded
+    assert_eq!(
+        shapes[5].as_ref().unwrap().top,
+        [value(Type::Boolean), value(Type::Character), None]
+    );
+    assert_eq!(shapes[5].as_ref().unwrap().min_depth, 3);
+}
+
+#[test]
+fn examples() {
+    // Only the loop printing a string of unknown length is warned about
+    let expected = [
+        ("examples/mandelbrot.txt", vec![]),
+        ("examples/digital_root.txt", vec![]),
+        (
+            "examples/fizzbuzz.txt",
+            vec![
+                (4, false, Error::StackOutOfBounds(0)),
+                (56, false, Error::StackOutOfBounds(0)),
+                (102, false, Error::StackOutOfBounds(0)),
+            ],
+        ),
+    ];
+    for (path, warned) in expected {
+        let source = std::fs::read_to_string(path).unwrap();
+        assert_eq!(warnings(&source), warned, "{path}");
+    }
+}
@@ -0,0 +1,32 @@
+use std::process::{Command, Output};
+
+/// Run the CLI with some arguments.
+fn pancake(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pancake"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn flags() {
+    let output = pancake(&["--seed", "1", "examples/hello_world.txt"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello, world!\n");
+    // Flags for other commands are rejected instead of doing nothing
+    for args in [
+        ["run", "--dot", "examples/hello_world.txt"],
+        ["debug", "--coverage", "out.info"],
+        ["check", "--top", "5"],
+    ] {
+        let output = pancake(&args);
+        assert!(!output.status.success());
+        let error = format!("Unknown flag {}\n", args[1]);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), error);
+    }
+    let output = pancake(&["--nope", "examples/hello_world.txt"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Unknown flag --nope\n"
+    );
+}
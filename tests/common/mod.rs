@@ -0,0 +1,23 @@
+use pancake::json::{read_message, write_message, Json};
+use std::io::{self, Cursor};
+use std::str::FromStr;
+
+/// Send messages to a server that frames JSON with `Content-Length` headers,
+/// returning the messages it sent back.
+pub fn session(
+    messages: impl IntoIterator<Item = String>,
+    serve: impl FnOnce(Cursor<Vec<u8>>, &mut Vec<u8>) -> io::Result<()>,
+) -> Vec<Json> {
+    let mut input = Vec::new();
+    for message in messages {
+        write_message(&mut input, &Json::from_str(&message).unwrap()).unwrap();
+    }
+    let mut output = Vec::new();
+    serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    let mut messages = Vec::new();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+    messages
+}
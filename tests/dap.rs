@@ -0,0 +1,215 @@
+mod common;
+
+use pancake::json::Json;
+use pancake::DapServer;
+use std::str::FromStr;
+
+/// Send requests to a debug adapter, returning the messages it sent back.
+fn session(requests: &[&str]) -> Vec<Json> {
+    let requests = requests
+        .iter()
+        .enumerate()
+        .map(|(seq, request)| format!("{{\"seq\":{},\"type\":\"request\",{request}}}", seq + 1));
+    common::session(requests, |input, output| {
+        DapServer::new(input, output).serve()
+    })
+}
+
+/// Summarize a message as its type, and either its command or its event.
+fn summary(message: &Json) -> String {
+    let get = |key| message.get(key).and_then(Json::as_str).unwrap_or_default();
+    match get("type") {
+        "response" => {
+            let success = message.get("success").and_then(Json::as_bool).unwrap();
+            format!(
+                "{} {}",
+                get("command"),
+                if success { "ok" } else { "failed" }
+            )
+        }
+        kind => format!("{kind} {}", get("event")),
+    }
+}
+
+fn body<'a>(messages: &'a [Json], summary_text: &str) -> Vec<&'a Json> {
+    messages
+        .iter()
+        .filter(|message| summary(message) == summary_text)
+        .map(|message| message.get("body").unwrap())
+        .collect()
+}
+
+#[test]
+fn debugging() {
+    let messages = session(&[
+        r#""command":"initialize","arguments":{"adapterID":"pancake"}"#,
+        r#""command":"launch","arguments":{"program":"examples/digital_root.txt","input":"12\n"}"#,
+        r#""command":"setBreakpoints","arguments":{"source":{"path":"examples/digital_root.txt"},"breakpoints":[{"line":75},{"line":200}]}"#,
+        r#""command":"setExceptionBreakpoints","arguments":{"filters":[]}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"stepIn","arguments":{"threadId":1}"#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"stepOut","arguments":{"threadId":1}"#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"scopes","arguments":{"frameId":0}"#,
+        r#""command":"variables","arguments":{"variablesReference":1}"#,
+        r#""command":"continue","arguments":{"threadId":1}"#,
+        r#""command":"disconnect""#,
+    ]);
+    let summaries: Vec<String> = messages.iter().map(summary).collect();
+    assert_eq!(
+        summaries,
+        [
+            "initialize ok",
+            "launch ok",
+            "event initialized",
+            "setBreakpoints ok",
+            "setExceptionBreakpoints ok",
+            "configurationDone ok",
+            "event stopped",
+            "stackTrace ok",
+            "stepIn ok",
+            "event stopped",
+            "stackTrace ok",
+            "stepOut ok",
+            "event stopped",
+            "stackTrace ok",
+            "scopes ok",
+            "variables ok",
+            "continue ok",
+            "event output",
+            "event exited",
+            "event terminated",
+            "disconnect ok",
+        ]
+    );
+    assert_eq!(
+        body(&messages, "setBreakpoints ok")[0].to_string(),
+        r#"{"breakpoints":[{"verified":true,"line":76},{"verified":false,"message":"There are no instructions after this line."}]}"#
+    );
+    let reasons: Vec<&str> = body(&messages, "event stopped")
+        .iter()
+        .map(|body| body.get("reason").and_then(Json::as_str).unwrap())
+        .collect();
+    assert_eq!(reasons, ["breakpoint", "step", "step"]);
+
+    // Each stack trace lists the label and line of every frame
+    let traces: Vec<Vec<(String, i64)>> = body(&messages, "stackTrace ok")
+        .iter()
+        .map(|body| {
+            let frames = body.get("stackFrames").and_then(Json::as_array).unwrap();
+            frames
+                .iter()
+                .map(|frame| {
+                    let name = frame.get("name").and_then(Json::as_str).unwrap();
+                    let line = frame.get("line").and_then(Json::as_i64).unwrap();
+                    (name.to_string(), line)
+                })
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        traces,
+        [
+            vec![("NUM_FINISHED".to_string(), 76)],
+            vec![("ABS_X".to_string(), 96), ("NUM_FINISHED".to_string(), 76)],
+            vec![("NUM_FINISHED".to_string(), 79)],
+        ]
+    );
+    assert_eq!(
+        body(&messages, "variables ok")[0].to_string(),
+        r#"{"variables":[{"name":"X","value":"3","variablesReference":0,"type":"integer"},{"name":"Y","value":"empty","variablesReference":0}]}"#
+    );
+    assert_eq!(
+        body(&messages, "event output")[0].to_string(),
+        r#"{"category":"stdout","output":"3\n"}"#
+    );
+    assert_eq!(
+        body(&messages, "event exited")[0].to_string(),
+        r#"{"exitCode":0}"#
+    );
+}
+
+#[test]
+fn breakpoint_on_entry() {
+    // The first instruction can be stopped at without stopping on entry
+    let messages = session(&[
+        r#""command":"initialize""#,
+        r#""command":"launch","arguments":{"program":"examples/truth_machine.txt","input":"false\n"}"#,
+        r#""command":"setBreakpoints","arguments":{"source":{"path":"examples/truth_machine.txt"},"breakpoints":[{"line":1}]}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"stackTrace","arguments":{"threadId":1}"#,
+        r#""command":"continue""#,
+    ]);
+    let reasons: Vec<&str> = body(&messages, "event stopped")
+        .iter()
+        .map(|body| body.get("reason").and_then(Json::as_str).unwrap())
+        .collect();
+    assert_eq!(reasons, ["breakpoint"]);
+    let frames = body(&messages, "stackTrace ok")[0]
+        .get("stackFrames")
+        .and_then(Json::as_array)
+        .unwrap();
+    assert_eq!(frames[0].get("line").and_then(Json::as_i64), Some(1));
+    assert_eq!(
+        body(&messages, "event output")[0].to_string(),
+        r#"{"category":"stdout","output":"false"}"#
+    );
+}
+
+#[test]
+fn runtime_errors() {
+    let messages = session(&[
+        r#""command":"initialize""#,
+        r#""command":"launch","arguments":{"program":"examples/digital_root.txt","input":"nope\n"}"#,
+        r#""command":"configurationDone""#,
+        r#""command":"continue""#,
+        r#""command":"launch","arguments":{"program":"missing.txt"}"#,
+    ]);
+    let summaries: Vec<String> = messages.iter().map(summary).collect();
+    assert_eq!(
+        summaries,
+        [
+            "initialize ok",
+            "launch ok",
+            "event initialized",
+            "configurationDone ok",
+            "event output",
+            "event stopped",
+            "continue ok",
+            "event exited",
+            "event terminated",
+            "launch failed",
+        ]
+    );
+    let stopped = body(&messages, "event stopped")[0];
+    assert_eq!(
+        stopped.get("reason").and_then(Json::as_str),
+        Some("exception")
+    );
+    assert_eq!(
+        body(&messages, "event exited")[0].to_string(),
+        r#"{"exitCode":1}"#
+    );
+}
+
+#[test]
+fn json() {
+    let text = r#" {"a": [1, -2.5e3, true, null], "b\"": "é🥞\n", "c": {}} "#;
+    let json = Json::from_str(text).unwrap();
+    assert_eq!(
+        json.get("a").and_then(Json::as_array).map(<[_]>::len),
+        Some(4)
+    );
+    assert_eq!(json.get("b\"").and_then(Json::as_str), Some("é🥞\n"));
+    assert_eq!(
+        json.to_string(),
+        r#"{"a":[1,-2500.0,true,null],"b\"":"é🥞\n","c":{}}"#
+    );
+    assert_eq!(Json::from_str(&json.to_string()), Ok(json));
+    let error = Json::from_str(r#"{"a" 1}"#).unwrap_err();
+    assert_eq!(error.to_string(), "expected `:` at byte 5");
+    assert!(Json::from_str("[1, 2").is_err());
+    assert!(Json::from_str("1 2").is_err());
+}
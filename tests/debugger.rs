@@ -0,0 +1,228 @@
+use pancake::{Debugger, Interpreter, Machine, MemoryIo, Outcome, Program, Recorder, Watch};
+
+/// Run debugger commands on a program, returning what the debugger wrote.
+fn debug(source: &str, commands: &[&str]) -> String {
+    let program = Program::parse(source).expect("parsing failed");
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::default());
+    let mut debugger = Debugger::new(Machine::new(&mut interpreter, &program), source);
+    let mut out = Vec::new();
+    for command in commands {
+        if !debugger.command(command, &mut out).unwrap() {
+            break;
+        }
+    }
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn stepping() {
+    let output = debug(
+        include_str!("../examples/hello_world.txt"),
+        &["step", "", "s 2", "p 0", "p X"],
+    );
+    assert_eq!(
+        output,
+        "  line 2 (instruction #1): push character #0A
+  line 3 (instruction #2): push character '!'
+  line 5 (instruction #4): push character 'l'
+0: d (character)
+X: empty
+"
+    );
+}
+
+#[test]
+fn breakpoints() {
+    let output = debug(
+        include_str!("../examples/hello_world.txt"),
+        &["break PRINTSTR", "b 27", "c", "c", "breakpoints", "delete 27", "c", "c"],
+    );
+    assert_eq!(
+        output,
+        "Breakpoint set at instruction #15.
+Breakpoint set at instruction #21.
+* line 18 (instruction #15): pop X
+* line 27 (instruction #21): write Y
+Instruction #15, at line 18
+Instruction #21, at line 27
+Deleted the breakpoint at instruction #21.
+* line 18 (instruction #15): pop X
+* line 18 (instruction #15): pop X
+"
+    );
+}
+
+#[test]
+fn modifying() {
+    let output = debug(
+        "\tpush integer 1\n\tpush integer 2\n\tpush integer 3\n\tpop X\n\tpop Y\n\tadd\n",
+        &[
+            "s 5",
+            "set X float 1.5",
+            "set Y empty",
+            "set 0 empty",
+            "set 0 boolean true",
+            "set 1 integer 3",
+            "p",
+            "c",
+            "q",
+            "p",
+        ],
+    );
+    assert_eq!(
+        output,
+        "  line 6 (instruction #5): add
+Set X.
+Set Y.
+Stack slots can't be empty.
+Set 0.
+There's no register or stack slot `1`.
+X: 1.5 (float)
+Y: empty
+Stack (1 values):
+\t0: true (boolean)
+Runtime error: encountered an unexpected empty register Y at line 6 (instruction #5: add)
+X: 1.5 (float)\tY: empty
+Stack (1 values): [true]
+"
+    );
+}
+
+#[test]
+fn recording() {
+    let program = Program::parse(
+        "\trandom integer X\n\tcopy X\n\tpush register X\n\toutput Y\n\tpush integer 7\n\tpop X\n\tswap X 0\n",
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::seeded(1).with_io(MemoryIo::default());
+    let mut machine = Machine::new(&mut interpreter, &program);
+    let mut recorder = Recorder::new();
+    while !recorder.step(&mut machine).is_stopped() {}
+    let end = (
+        machine.interpreter.x.clone(),
+        machine.interpreter.stack.clone(),
+    );
+    assert_eq!(recorder.deltas().len(), 7);
+
+    // Going back undoes everything
+    recorder.seek(&mut machine, 0);
+    assert_eq!(machine.pc(), 0);
+    assert_eq!(machine.steps(), 0);
+    assert_eq!(machine.outcome(), None);
+    assert_eq!(
+        (&machine.interpreter.x, &machine.interpreter.y),
+        (&None, &None)
+    );
+    assert!(machine.interpreter.stack.is_empty());
+
+    // Replaying gives the same random values, without writing output again
+    recorder.seek(&mut machine, 7);
+    assert_eq!(
+        (
+            machine.interpreter.x.clone(),
+            machine.interpreter.stack.clone()
+        ),
+        end
+    );
+    assert_eq!(machine.outcome(), Some(&Outcome::Halted));
+    let output = machine.interpreter.io.output.clone();
+    assert_eq!(output, end.0.unwrap().to_string().as_bytes());
+
+    // The swap changed the bottom of the stack, and popping into X before it did too
+    assert_eq!(recorder.last_change(&mut machine, Watch::Stack(0)), Some(6));
+    assert_eq!(recorder.position(), 6);
+    assert_eq!(recorder.last_change(&mut machine, Watch::X), Some(5));
+    assert_eq!(recorder.last_change(&mut machine, Watch::Y), Some(3));
+    assert_eq!(recorder.last_change(&mut machine, Watch::Y), Some(1));
+    assert_eq!(machine.interpreter.y, None);
+    assert_eq!(recorder.last_change(&mut machine, Watch::Y), None);
+}
+
+#[test]
+fn reverse_debugging() {
+    let output = debug(
+        include_str!("../examples/hello_world.txt"),
+        &[
+            "s 20",
+            "last X",
+            "back",
+            "history",
+            "b 1",
+            "replay 20",
+            "rc",
+            "last X",
+        ],
+    );
+    assert_eq!(
+        output,
+        "  line 25 (instruction #20): branch END
+X changes from false (boolean) to empty here.
+  line 23 (instruction #19): push register X
+  line 22 (instruction #18): not X
+At instruction 18 of 20 recorded.
+Breakpoint set at instruction #0.
+  line 25 (instruction #20): branch END
+* line 1 (instruction #0): push character #00
+X never changed in the recording.
+"
+    );
+}
+
+#[test]
+fn failed_set_keeps_recording() {
+    let output = debug(
+        include_str!("../examples/hello_world.txt"),
+        &[
+            "s 3",
+            "back",
+            "set 9 integer 1",
+            "set X nope 1",
+            "history",
+            "set X integer 1",
+            "history",
+        ],
+    );
+    assert_eq!(
+        output,
+        "  line 4 (instruction #3): push character 'd'
+  line 3 (instruction #2): push character '!'
+There's no register or stack slot `9`.
+Could not parse `nope 1` as a value.
+At instruction 2 of 3 recorded.
+Set X.
+At instruction 2 of 2 recorded.
+"
+    );
+}
+
+#[test]
+fn reading_program() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // Commands come from stdin, so the program's input has to come from a file
+    let input = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("debugger_input.txt");
+    std::fs::write(&input, "false\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pancake"))
+        .args(["debug", "--input"])
+        .arg(&input)
+        .arg("examples/truth_machine.txt")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"step\nprint X\ncontinue\n").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Debugging 11 instructions. Type `help` for a list of commands.
+  line 1 (instruction #0): input boolean X
+(pancake)   line 2 (instruction #1): push register X
+(pancake) X: false (boolean)
+(pancake) falseThe program hit a break.
+(pancake) "
+    );
+}
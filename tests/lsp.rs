@@ -0,0 +1,178 @@
+mod common;
+
+use pancake::json::Json;
+use pancake::LspServer;
+
+const URI: &str = "file:///program.txt";
+
+const SOURCE: &str = "    call PRINT
+    break
+PRINT
+    pop X
+    output X
+    return
+";
+
+/// Send messages to a language server, returning the messages it sent back.
+/// Messages with an ID are requests, and the rest are notifications.
+fn session(messages: &[&str]) -> Vec<Json> {
+    let messages = (messages.iter()).map(|message| format!("{{\"jsonrpc\":\"2.0\",{message}}}"));
+    common::session(messages, |input, output| {
+        LspServer::new(input, output).serve()
+    })
+}
+
+/// Create a didOpen notification for a document.
+fn open(text: &str) -> String {
+    let document = Json::object([
+        ("uri", URI.into()),
+        ("languageId", "pancake".into()),
+        ("version", 1i64.into()),
+        ("text", text.into()),
+    ]);
+    format!(r#""method":"textDocument/didOpen","params":{{"textDocument":{document}}}"#)
+}
+
+/// Create a request about a position in the document.
+fn request(id: i64, method: &str, line: usize, character: usize) -> String {
+    format!(
+        r#""id":{id},"method":"textDocument/{method}","params":{{"textDocument":{{"uri":"{URI}"}},"position":{{"line":{line},"character":{character}}},"context":{{"includeDeclaration":true}}}}"#
+    )
+}
+
+/// Get the result of the response to a request.
+fn result(messages: &[Json], id: i64) -> &Json {
+    messages
+        .iter()
+        .find(|message| message.get("id") == Some(&Json::Integer(id)))
+        .and_then(|message| message.get("result"))
+        .unwrap()
+}
+
+/// Get the start line of every location.
+fn lines(locations: &Json) -> Vec<i64> {
+    locations
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| {
+            let start = location.get("range").unwrap().get("start").unwrap();
+            start.get("line").unwrap().as_i64().unwrap()
+        })
+        .collect()
+}
+
+/// Get the labels of every completion item.
+fn labels(items: &Json) -> Vec<&str> {
+    items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item.get("label").unwrap().as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn diagnostics() {
+    let change = Json::object([(
+        "contentChanges",
+        Json::Array(vec![Json::object([("text", SOURCE.into())])]),
+    )]);
+    let change = change.to_string().replacen(
+        '{',
+        &format!(r#"{{"textDocument":{{"uri":"{URI}","version":2}},"#),
+        1,
+    );
+    let messages = session(&[
+        r#""id":1,"method":"initialize","params":{"capabilities":{}}"#,
+        r#""method":"initialized","params":{}"#,
+        &open("    push integer 1\n    jump NOWHERE\n    pop Z\n"),
+        &format!(r#""method":"textDocument/didChange","params":{change}"#),
+        r#""id":2,"method":"shutdown""#,
+        r#""method":"exit""#,
+    ]);
+    let capabilities = result(&messages, 1).get("capabilities").unwrap();
+    assert_eq!(
+        capabilities.get("definitionProvider"),
+        Some(&Json::Boolean(true))
+    );
+    let published: Vec<&Json> = messages
+        .iter()
+        .filter(|message| {
+            message.get("method").and_then(Json::as_str) == Some("textDocument/publishDiagnostics")
+        })
+        .map(|message| message.get("params").unwrap().get("diagnostics").unwrap())
+        .collect();
+    assert_eq!(published.len(), 2);
+    let errors = published[0].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0].get("range").unwrap().to_string(),
+        r#"{"start":{"line":1,"character":9},"end":{"line":1,"character":16}}"#
+    );
+    assert_eq!(errors[1].get("severity"), Some(&Json::Integer(1)));
+    // Fixing the program clears them
+    assert_eq!(published[1], &Json::Array(Vec::new()));
+    assert_eq!(result(&messages, 2), &Json::Null);
+}
+
+#[test]
+fn navigation() {
+    let messages = session(&[
+        &open(SOURCE),
+        &request(1, "definition", 0, 10),
+        &request(2, "references", 2, 2),
+        r#""id":3,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///program.txt"}}"#,
+        &request(4, "definition", 1, 6),
+        r#""id":5,"method":"workspace/symbol","params":{"query":""}"#,
+    ]);
+    let definition = result(&messages, 1);
+    assert_eq!(
+        definition.get("range").unwrap().to_string(),
+        r#"{"start":{"line":2,"character":0},"end":{"line":2,"character":5}}"#
+    );
+    assert_eq!(lines(result(&messages, 2)), [0, 2]);
+    let symbols = result(&messages, 3).as_array().unwrap();
+    assert_eq!(symbols.len(), 1);
+    assert_eq!(symbols[0].get("name").unwrap().as_str(), Some("PRINT"));
+    // Instructions aren't labels
+    assert_eq!(result(&messages, 4), &Json::Null);
+    let unsupported = messages.last().unwrap().get("error").unwrap();
+    assert_eq!(unsupported.get("code"), Some(&Json::Integer(-32601)));
+}
+
+#[test]
+fn hover_and_completion() {
+    let messages = session(&[
+        &open("    push float 1.5\n    call \nPRINT\n    pop X\n    output \n    cop\n"),
+        &request(1, "hover", 0, 6),
+        &request(2, "hover", 3, 5),
+        &request(3, "completion", 1, 9),
+        &request(4, "completion", 4, 11),
+        &request(5, "completion", 5, 7),
+        // The empty line after the final newline
+        &request(6, "completion", 6, 0),
+    ]);
+    let hover = |id| {
+        let contents = result(&messages, id).get("contents").unwrap();
+        contents.get("value").unwrap().as_str().unwrap().to_string()
+    };
+    assert_eq!(
+        hover(1),
+        "```\npush float 1.5\n```\nPush a floating point. Double precision IEEE754. NaN and +/- Infinity are pushable using this."
+    );
+    assert!(hover(2).starts_with("```\npop X\n```\n"));
+    assert_eq!(labels(result(&messages, 3)), ["PRINT"]);
+    assert_eq!(
+        labels(result(&messages, 4)),
+        ["integer", "float", "boolean", "character", "X", "Y"]
+    );
+    let instructions = labels(result(&messages, 5));
+    assert!(instructions.contains(&"copy"));
+    assert!(instructions.contains(&"input?"));
+    assert_eq!(
+        instructions.iter().filter(|&&name| name == "push").count(),
+        1
+    );
+    assert_eq!(labels(result(&messages, 6)), instructions);
+}
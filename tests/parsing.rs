@@ -1,12 +1,12 @@
 use std::cmp::Ordering::*;
-use pancake::{Instruction::*, Register::*, Type::*};
+use pancake::{Instruction::*, ParseError, ParseErrorKind, Register::*, Type::*};
 
 #[test]
 fn parsing_test() {
     let program = include_str!("test.txt");
     let parsed = pancake::parse_file(program).expect("parsing failed");
     assert_eq!(
-        parsed,
+        parsed.into_iter().map(|(_, instr)| instr).collect::<Vec<_>>(),
         vec![
             PushInteger(100),
             PushFloat(-1.5e9),
@@ -50,3 +50,99 @@ fn parsing_test() {
         ]
     )
 }
+
+#[test]
+fn parse_errors() {
+    let error = pancake::parse_file("START\n\tpush integer 1\n\tpop Z\n").unwrap_err();
+    assert_eq!(
+        error,
+        ParseError {
+            line: 2,
+            columns: 5..6,
+            token: "Z".to_string(),
+            kind: ParseErrorKind::Expected("register X or Y"),
+        }
+    );
+    let error = pancake::parse_file("\tjump NOWHERE\n").unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::MissingLabel("NOWHERE".to_string()));
+    assert_eq!(error.columns, 6..13);
+    let error = pancake::parse_file("    frobnicate X\n").unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::UnknownInstruction);
+    assert_eq!(error.token, "frobnicate");
+    let error = pancake::parse_file("\tpush character\n").unwrap_err();
+    assert_eq!(error.to_string(), "expected character like 'H' or #48, found end of line");
+}
+
+#[test]
+fn rendering() {
+    let source = "START\n    cast string X\n";
+    let error = pancake::parse_file(source).unwrap_err();
+    assert_eq!(
+        error.render(source),
+        "expected type integer, float, boolean or character, found `string`
+ --> line 2, column 10
+  |
+2 |     cast string X
+  |          ^^^^^^"
+    );
+}
+
+#[test]
+fn all_errors() {
+    let errors = pancake::Program::parse_all(
+        "START\n\tpush integer one\n\tadd\n\tjump END\n\tpush character 'ab'\n\tpop X\n",
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors.iter().map(|err| (err.line, err.kind.clone())).collect::<Vec<_>>(),
+        vec![
+            (1, ParseErrorKind::Expected("integer")),
+            (3, ParseErrorKind::MissingLabel("END".to_string())),
+            (4, ParseErrorKind::Expected("character like 'H' or #48")),
+        ]
+    );
+}
+
+#[test]
+fn label_errors() {
+    let errors = pancake::Program::parse_all(
+        "LOOP\n\tjump LOOP\nLOOP\n\tjump END\nEND * the end\n\n* Not a label\n",
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors.iter().map(|err| (err.line, err.kind.clone())).collect::<Vec<_>>(),
+        vec![
+            (2, ParseErrorKind::DuplicateLabel("LOOP".to_string(), 0)),
+            (4, ParseErrorKind::InvalidLabel),
+        ]
+    );
+    assert_eq!(errors[1].to_string(), "label `END * the end` must not contain whitespace");
+}
+
+#[test]
+fn trying_input() {
+    let parsed = pancake::parse_file("\tinput? integer X\n\tread? character Y\n").unwrap();
+    assert_eq!(parsed, vec![(0, TryInput(Integer, X)), (1, TryRead(Character, Y))]);
+}
+
+#[test]
+fn displaying() {
+    let instructions = pancake::parse_file(include_str!("test.txt")).unwrap();
+    for (_, instr) in instructions {
+        let text = instr.to_string();
+        match instr {
+            Jump(index) | Branch(index) | Call(index) => {
+                assert!(text.ends_with(&format!(" #{index}")), "{text}")
+            }
+            _ => assert_eq!(
+                pancake::Instruction::parse(&text, &Default::default()),
+                Ok(Some(instr)),
+                "{text}"
+            ),
+        }
+    }
+    assert_eq!(PushCharacter(b' ').to_string(), "push character #20");
+    assert_eq!(PushFloat(2.0).to_string(), "push float 2.0");
+    assert_eq!(Pop(None).to_string(), "pop _");
+    assert_eq!(Jump(3).to_string(), "jump #3");
+}
@@ -0,0 +1,110 @@
+use pancake::{Coverage, Interpreter, MemoryIo, Outcome, Profiler, Program};
+
+const SOURCE: &str = "\tcall COUNT
+\tjump END
+COUNT
+\tpush integer 3
+LOOP
+\tpop X
+\tpush integer 1
+\tpop Y
+\tsubtract
+\tpop X
+\tcopy X
+\tpush register Y
+\tpush integer 0
+\tpop Y
+\tcompare greater
+\tbranch LOOP
+\tpop _
+\treturn
+END
+";
+
+fn profile(source: &str) -> (Program, Profiler) {
+    let program = Program::parse(source).unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::default());
+    let mut profiler = Profiler::new(&program);
+    assert_eq!(
+        interpreter.run_observed(&program, &mut profiler),
+        Outcome::Halted
+    );
+    (program, profiler)
+}
+
+#[test]
+fn counting() {
+    let (_, profiler) = profile(SOURCE);
+    assert_eq!(
+        profiler.executions(),
+        [1, 1, 1, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 1, 1]
+    );
+    let regions: Vec<(&str, usize, u64)> = profiler
+        .regions()
+        .iter()
+        .map(|region| (region.name.as_str(), region.start, region.executions))
+        .collect();
+    assert_eq!(
+        regions,
+        [
+            ("<start>", 0, 2),
+            ("COUNT", 2, 1),
+            ("LOOP", 3, 35),
+            ("END", 16, 0)
+        ]
+    );
+}
+
+#[test]
+fn folding() {
+    let (_, profiler) = profile(SOURCE);
+    assert_eq!(profiler.folded(), "<start> 2\n<start>;COUNT 36\n");
+}
+
+#[test]
+fn reporting() {
+    let (program, profiler) = profile(SOURCE);
+    let report = profiler.report(&program, SOURCE, 2);
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "38 instructions executed.");
+    assert_eq!(lines[3], "           3   7.89%  line 6     pop X");
+    assert_eq!(lines[4], "           3   7.89%  line 7     push integer 1");
+    assert_eq!(lines[5], "");
+    assert!(lines[7].contains("LOOP (line 6)"), "{}", lines[7]);
+    assert!(lines[8].contains("<start> (line 1)"), "{}", lines[8]);
+    assert!(lines[9].contains("COUNT (line 4)"), "{}", lines[9]);
+    assert_eq!(lines.len(), 10);
+}
+
+#[test]
+fn coverage() {
+    let program = Program::parse(SOURCE).unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::default());
+    let mut coverage = Coverage::new(&program);
+    assert_eq!(
+        interpreter.run_observed(&program, &mut coverage),
+        Outcome::Halted
+    );
+    assert_eq!(coverage.branch(13), (2, 1));
+    assert_eq!(coverage.branch(12), (0, 0));
+    let mut expected = String::from(
+        "TN:\nSF:count.txt\nFN:4,COUNT\nFN:6,LOOP\nFNDA:1,COUNT\nFNDA:3,LOOP\nFNF:2\nFNH:2\n\
+        BRDA:16,13,0,2\nBRDA:16,13,1,1\nBRF:2\nBRH:2\nDA:1,1\nDA:2,1\nDA:4,1\n",
+    );
+    for line in 6..=16 {
+        expected.push_str(&format!("DA:{line},3\n"));
+    }
+    expected.push_str("DA:17,1\nDA:18,1\nLF:16\nLH:16\nend_of_record\n");
+    assert_eq!(coverage.lcov(&program, "count.txt"), expected);
+
+    // Nothing runs after the break, so the branch is written with dashes
+    let program = Program::parse("\tbreak\nLOOP\n\tpush boolean true\n\tbranch LOOP\n").unwrap();
+    let mut coverage = Coverage::new(&program);
+    interpreter.run_observed(&program, &mut coverage);
+    let lcov = coverage.lcov(&program, "loop.txt");
+    assert!(
+        lcov.contains("BRDA:4,2,0,-\nBRDA:4,2,1,-\nBRF:2\nBRH:0\n"),
+        "{lcov}"
+    );
+    assert!(lcov.contains("LF:3\nLH:1\n"), "{lcov}");
+}
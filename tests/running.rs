@@ -0,0 +1,348 @@
+use pancake::{
+    Error, InputRetry, Instruction::*, Interpreter, JumpKind, Machine, MemoryIo, Observer, Outcome,
+    Program, Register::*, RuntimeError, ScriptedIo, State, StepResult, Trace, TraceHook, Type::*,
+    Value,
+};
+
+/// Get where a program errored, panicking if it didn't.
+fn errored(outcome: Outcome) -> (usize, usize, Error) {
+    let Outcome::Errored(err) = outcome else {
+        panic!("expected an error, got {outcome:?}");
+    };
+    (err.index, err.line, err.error)
+}
+
+fn run(source: &str, input: &[u8]) -> (Outcome, Vec<u8>) {
+    let program = Program::parse(source).expect("parsing failed");
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::new(input));
+    let outcome = interpreter.run(&program);
+    (outcome, interpreter.io.output)
+}
+
+#[test]
+fn hello_world() {
+    let (outcome, output) = run(include_str!("../examples/hello_world.txt"), b"");
+    assert_eq!(outcome, Outcome::Halted);
+    assert_eq!(output, b"Hello, world!\n");
+}
+
+#[test]
+fn break_outcome() {
+    let (outcome, output) = run(include_str!("../examples/truth_machine.txt"), b"false\n");
+    assert_eq!(outcome, Outcome::Break);
+    assert_eq!(output, b"false");
+}
+
+#[test]
+fn error_outcome() {
+    let (outcome, _) = run("\tpush integer 1\n\n\toutput X\n", b"");
+    assert_eq!(errored(outcome), (1, 2, Error::EmptyRegister(X)));
+}
+
+#[test]
+fn error_report() {
+    const SOURCE: &str =
+        "\tpush integer 1\n\tpush integer 2\n\tpush float 0.5\n\tpop X\n\tpush integer 3\n\tpop Y\n\tadd\n";
+    let (outcome, _) = run(SOURCE, b"");
+    let Outcome::Errored(err) = outcome else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.instruction, Add);
+    assert_eq!(err.stack_top, vec![1i64.into(), 2i64.into()]);
+    assert_eq!(
+        err.to_string(),
+        "failed to operate with types float and integer at line 7 (instruction #6: add)
+X: 0.5 (float)\tY: 3 (integer)
+Stack (2 values): [1, 2]"
+    );
+    assert_eq!(
+        err.render(SOURCE),
+        "failed to operate with types float and integer at line 7 (instruction #6: add)
+  |
+7 | add
+  |
+X: 0.5 (float)\tY: 3 (integer)
+Stack (2 values): [1, 2]"
+    );
+    // The values the instruction was given are shown, even if it took them
+    let (outcome, _) = run("\tpush integer 1\n\tpush boolean true\n\treturn\n", b"");
+    let Outcome::Errored(err) = outcome else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.stack_top, vec![1i64.into(), true.into()]);
+    assert_eq!(err.stack_length, 2);
+    // Jumps name their labels, like in the source
+    let (outcome, _) = run("\tbranch END\nEND\n\tbreak\n", b"");
+    let Outcome::Errored(err) = outcome else {
+        panic!("expected an error");
+    };
+    assert!(err.to_string().contains("(instruction #0: branch END)"));
+}
+
+#[test]
+fn labels() {
+    let program = Program::parse(include_str!("test.txt")).expect("parsing failed");
+    assert_eq!(program.label("START"), Some(0));
+    assert_eq!(program.label("END"), Some(39));
+    assert_eq!(program.label("MIDDLE"), None);
+    assert_eq!(program.enclosing_label(38), Some("START"));
+    assert_eq!(program.enclosing_label(39), Some("END"));
+}
+
+#[test]
+fn stepping() {
+    let program = Program::parse(include_str!("../examples/truth_machine.txt")).unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::new("true\n"));
+    let mut machine = Machine::new(&mut interpreter, &program);
+    assert_eq!(machine.current(), Some((0, Input(Boolean, X))));
+    assert_eq!(machine.step(), StepResult::Continue);
+    assert_eq!(machine.step(), StepResult::Continue);
+    assert_eq!(machine.pc(), 2);
+    assert_eq!(machine.step(), StepResult::Jumped(7));
+    assert_eq!(machine.current(), Some((8, PushBoolean(true))));
+    assert_eq!(machine.outcome(), None);
+}
+
+#[test]
+fn stepping_stops() {
+    let program = Program::parse("\tpush integer 1\n\tbreak\n\tpush integer 2\n").unwrap();
+    let mut interpreter = Interpreter::default();
+    let mut machine = Machine::new(&mut interpreter, &program);
+    assert_eq!(machine.step(), StepResult::Continue);
+    assert_eq!(machine.step(), StepResult::Break);
+    assert_eq!(machine.step(), StepResult::Break);
+    assert_eq!(machine.outcome(), Some(&Outcome::Break));
+    machine.set_pc(2);
+    assert_eq!(machine.step(), StepResult::Halted);
+    assert_eq!(interpreter.stack, vec![1i64.into(), 2i64.into()]);
+}
+
+#[test]
+fn returning_negative_halts() {
+    let (outcome, _) = run("\tpush integer -5\n\treturn\n\tpush integer 1\n", b"");
+    assert_eq!(outcome, Outcome::Halted);
+}
+
+#[test]
+fn fuel() {
+    let program = Program::parse(include_str!("../examples/truth_machine.txt")).unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::new("true\n"));
+    interpreter.max_steps = Some(100);
+    assert_eq!(
+        errored(interpreter.run(&program)),
+        (8, 9, Error::FuelExhausted(100))
+    );
+    assert_eq!(interpreter.io.output, b"true".repeat(24));
+}
+
+#[test]
+fn stack_limit() {
+    let program = Program::parse("LOOP\n\tpush integer 1\n\tjump LOOP\n").unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::default());
+    interpreter.max_stack = Some(16);
+    assert_eq!(
+        errored(interpreter.run(&program)),
+        (0, 1, Error::StackOverflow(16))
+    );
+    assert_eq!(interpreter.stack.len(), 16);
+}
+
+#[test]
+fn seeded() {
+    let program = Program::parse(
+        "\trandom integer X\n\toutput X\n\trandom float X\n\toutput X\n\trandom boolean X\n\toutput X\n",
+    )
+    .unwrap();
+    let run = |seed| {
+        let mut interpreter = Interpreter::seeded(seed).with_io(MemoryIo::default());
+        interpreter.run(&program);
+        interpreter.io.output
+    };
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+}
+
+#[test]
+fn end_of_input() {
+    let (outcome, output) = run("LOOP\n\tread character X\n\twrite X\n\tjump LOOP\n", b"meow");
+    assert_eq!(errored(outcome), (0, 1, Error::EndOfInput));
+    assert_eq!(output, b"meow");
+    let (outcome, _) = run("\tinput integer X\n", b"");
+    assert_eq!(errored(outcome), (0, 0, Error::EndOfInput));
+}
+
+#[test]
+fn trying_input() {
+    let (outcome, output) = run(include_str!("../examples/cat.txt"), b"meow\n");
+    assert_eq!(outcome, Outcome::Break);
+    assert_eq!(output, b"meow\n");
+    let program = Program::parse("\tinput? integer X\n\tread? integer Y\n").unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::new("12\n"));
+    assert_eq!(interpreter.run(&program), Outcome::Halted);
+    assert_eq!(interpreter.x, Some(12i64.into()));
+    assert_eq!(interpreter.y, None);
+    assert_eq!(interpreter.stack, vec![true.into(), false.into()]);
+}
+
+#[test]
+fn mixed_input() {
+    const SOURCE: &str =
+        "\tinput integer X\n\tread character Y\n\toutput X\n\twrite Y\n\tinput boolean X\n\toutput X\n";
+    let (outcome, output) = run(SOURCE, b"nope\n12\nZtrue");
+    assert_eq!(outcome, Outcome::Halted);
+    assert_eq!(output, b"12Ztrue");
+
+    /// A reader that only gives out a byte at a time.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    let program = Program::parse(SOURCE).unwrap();
+    let input = Trickle(b"12\r\nZ\nfalse\n");
+    let mut interpreter = Interpreter::default().with_io((input, Vec::new()));
+    assert_eq!(interpreter.run(&program), Outcome::Halted);
+    assert_eq!(interpreter.io.1, b"12Zfalse");
+}
+
+#[test]
+fn scripted_input() {
+    let program = Program::parse(include_str!("../examples/digital_root.txt")).unwrap();
+    let mut interpreter = Interpreter::default().with_io(ScriptedIo::new(["oops", "493193"]));
+    assert_eq!(interpreter.run(&program), Outcome::Halted);
+    assert_eq!(interpreter.io.output, b"2\n");
+    assert_eq!(interpreter.io.remaining_lines(), 0);
+}
+
+#[test]
+fn input_retries() {
+    let program = Program::parse("\tinput integer X\n").unwrap();
+    let run = |retry| {
+        let mut interpreter = Interpreter::default().with_io(ScriptedIo::new(["one", "two", "3"]));
+        interpreter.input_retry = retry;
+        (interpreter.run(&program), interpreter.x)
+    };
+    assert_eq!(run(InputRetry::Forever), (Outcome::Halted, Some(3i64.into())));
+    assert_eq!(run(InputRetry::Times(2)), (Outcome::Halted, Some(3i64.into())));
+    assert_eq!(
+        errored(run(InputRetry::Times(1)).0),
+        (0, 0, Error::InvalidInput(Integer, "two".to_string()))
+    );
+    assert_eq!(
+        errored(run(InputRetry::Never).0),
+        (0, 0, Error::InvalidInput(Integer, "one".to_string()))
+    );
+}
+
+#[test]
+fn tracing() {
+    let program =
+        Program::parse("\tpush integer 2\n\tpop X\n\tcopy X\n\tpush register Y\n\tadd\n").unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::default());
+    let mut lines = Vec::new();
+    let outcome = interpreter.run_traced(&program, |trace| {
+        lines.push(trace.to_json(&program, trace.index == 3).to_string())
+    });
+    assert_eq!(errored(outcome).0, 4);
+    assert_eq!(
+        lines,
+        [
+            r#"{"step":0,"index":0,"line":1,"instruction":"push integer 2","x":null,"y":null,"depth":1}"#,
+            r#"{"step":1,"index":1,"line":2,"instruction":"pop X","x":{"type":"integer","value":2},"y":null,"depth":0}"#,
+            r#"{"step":2,"index":2,"line":3,"instruction":"copy X","x":{"type":"integer","value":2},"y":{"type":"integer","value":2},"depth":0}"#,
+            r#"{"step":3,"index":3,"line":4,"instruction":"push register Y","x":{"type":"integer","value":2},"y":null,"depth":1,"stack":[{"type":"integer","value":2}]}"#,
+            r#"{"step":4,"index":4,"line":5,"instruction":"add","x":null,"y":null,"depth":1,"error":"encountered an unexpected empty register Y"}"#,
+        ]
+    );
+    // Labels are written by name, like in the source
+    let program = Program::parse("\tjump END\nEND\n\tbreak\n").unwrap();
+    let mut instructions = Vec::new();
+    interpreter.run_traced(&program, |trace| {
+        instructions.push(trace.to_json(&program, false).get("instruction").cloned())
+    });
+    assert_eq!(
+        instructions,
+        [Some("jump END".into()), Some("break".into())]
+    );
+}
+
+#[test]
+fn observing() {
+    /// Writes down everything it sees.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Observer for Log {
+        fn before(&mut self, state: &State) {
+            self.0.push(format!("before {}", state.index));
+        }
+
+        fn after(&mut self, trace: &Trace) {
+            self.0.push(format!("after {}", trace.index));
+        }
+
+        fn jumped(&mut self, kind: JumpKind, from: usize, to: usize) {
+            self.0.push(format!("{kind:?} {from} -> {to}"));
+        }
+
+        fn input(&mut self, index: usize, value: &Value) {
+            self.0.push(format!("input {index}: {value}"));
+        }
+
+        fn output(&mut self, index: usize, value: &Value) {
+            self.0.push(format!("output {index}: {value}"));
+        }
+
+        fn errored(&mut self, error: &RuntimeError) {
+            self.0.push(format!("errored {}: {}", error.index, error.error));
+        }
+    }
+
+    let program = Program::parse(
+        "\tcall PRINT\n\tpush boolean false\n\tbranch PRINT\n\tjump END\nPRINT\n\tinput integer X\n\toutput X\n\treturn\nEND\n\tpop X\n",
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::default().with_io(MemoryIo::new("5\n"));
+    let (mut log, mut steps) = (Log::default(), 0);
+    let outcome = interpreter.run_observed(
+        &program,
+        (&mut log, TraceHook(|_: &Trace| steps += 1)),
+    );
+    assert_eq!(errored(outcome), (7, 9, Error::StackOutOfBounds(0)));
+    assert_eq!(
+        log.0,
+        [
+            "before 0",
+            "Call 0 -> 4",
+            "after 0",
+            "before 4",
+            "input 4: 5",
+            "after 4",
+            "before 5",
+            "output 5: 5",
+            "after 5",
+            "before 6",
+            "Return 6 -> 1",
+            "after 6",
+            "before 1",
+            "after 1",
+            "before 2",
+            "after 2",
+            "before 3",
+            "Jump 3 -> 7",
+            "after 3",
+            "before 7",
+            "after 7",
+            "errored 7: failed to access stack value #0",
+        ]
+    );
+    assert_eq!(steps, 8);
+}